@@ -1,4 +1,4 @@
-extern "C" void print_value(char tag, double value);
+extern "C" void print_value(void* stream, char tag, double value);
 
 union Value {
   double float_value;
@@ -9,5 +9,5 @@ int main() {
   double v = 1.2;
   union Value val;
   val.str_value= (char*) &v;
-  print_value(0, val.float_value);
+  print_value(nullptr, 0, val.float_value);
 }
@@ -2,8 +2,16 @@
 #include <string>
 #include <iostream>
 #include <vector>
-#include <fstream>
+#include <unordered_map>
+#include <cstring>
+#include <cstdlib>
 #include <math.h>
+#include <regex.h>
+#include <sys/wait.h>
+#include <random>
+#include <ctime>
+#include <unistd.h>
+#include <clocale>
 
 #define DEBUG 1
 
@@ -13,21 +21,88 @@
 #define PRINT(...) do{ } while ( false )
 #endif
 
-static char CONVFMT[] = "%.6g";
-
 union Value {
   double float_value;
   char* str_value;
 };
 
+// Tags: 0 is a number, 1 an owned string, 2 a constant string, 3 an owned string read from
+// input that looks like a number (a strnum) and 4 an uninitialized value, which is both "" and 0.
+static bool owns_string(char tag) {
+  return tag == 1 || tag == 3;
+}
+
+// Values that take part in a comparison as numbers
+static bool compares_as_number(char tag) {
+  return tag == 0 || tag == 3 || tag == 4;
+}
+
 static char empty_string[] = "";
-static char FS = ' ';
-static char RS = '\n';
+
+// An element of an awk array. Strings (tag 1) are owned by the array.
+struct Cell {
+  char tag;
+  double value;
+};
+typedef std::unordered_map<std::string, Cell> Array;
+
+// Special variables. Each is a tag and value like any other awk value and owns its string. The
+// compiled program reads and assigns them in place so they are exported with C names.
+#define SPECIAL(name) char special_##name##_tag = 0; double special_##name##_value = 0;
+extern "C" {
+  SPECIAL(NR)
+  SPECIAL(FNR)
+  SPECIAL(NF)
+  SPECIAL(FILENAME)
+  SPECIAL(RSTART)
+  SPECIAL(RLENGTH)
+  SPECIAL(SUBSEP)
+  SPECIAL(ARGC)
+  SPECIAL(FS)
+  SPECIAL(RS)
+  SPECIAL(RT)
+  SPECIAL(CONVFMT)
+  SPECIAL(OFMT)
+  SPECIAL(OFS)
+  // Arrays, created by init
+  void* special_ENVIRON = nullptr;
+  void* special_ARGV = nullptr;
+}
 
 static std::string full_line;
 static std::vector<std::string> fields;
+// Files from the command line, last first. init copies them into ARGV.
 static std::vector<std::string> files;
-static std::ifstream current_file;
+
+// An open input file or command (cmd | getline) keyed by the name the program used for it
+struct InputStream {
+  FILE* file;
+  bool is_command;
+};
+static std::unordered_map<std::string, InputStream> inputs;
+// Input read past the end of a record, returned by the next read from the same stream
+static std::unordered_map<FILE*, std::string> pending_input;
+// An open output file or command (print | cmd) keyed by the name the program used for it
+struct OutputStream {
+  FILE* file;
+  bool is_command;
+};
+static std::unordered_map<std::string, OutputStream> outputs;
+// The main input is read from the file named current_filename. It is kept out of inputs so
+// `getline < FILENAME` and close(FILENAME) open and close a stream of their own.
+static std::string current_filename;
+static FILE* main_input = nullptr;
+// The next ARGV index to read input from, and whether any ARGV entry has been used as input
+static long next_arg = 1;
+static bool read_file_operand = false;
+// Set once stdin is used as the main input because ARGV named no files
+static bool read_stdin = false;
+static std::unordered_map<std::string, regex_t*> regex_cache;
+static const size_t MAX_CACHED_REGEXES = 500;
+
+// The length of each string value holding a NUL, eg. RS = "\0" or sprintf("%c", 0). As a C
+// string it would end at the NUL.
+static std::unordered_map<const char*, size_t> nul_string_lengths;
 
 // Returns a malloc'ed C style null terminated string to be passed across
 // ffi to llvm program. llvm program is responsible for calling free_string
@@ -37,15 +112,167 @@ char* owned_string(std::string data) {
   char* pointer_to_existing = (char*) data.c_str();
   char* new_string = (char*) malloc(allocation_size);
   memcpy(new_string, pointer_to_existing, allocation_size);
+  if (data.find('\0') != std::string::npos) {
+    nul_string_lengths[new_string] = data.length();
+  }
   return new_string;
 }
 
+// A string literal holding a NUL. Called each time the literal is used.
+extern "C" void string_with_nul(const char* str, long long length) {
+  nul_string_lengths[str] = (size_t) length;
+}
+
+// The contents of a string value, including any NUL it holds
+static std::string string_contents(const char* str) {
+  if (!nul_string_lengths.empty()) {
+    auto length = nul_string_lengths.find(str);
+    if (length != nul_string_lengths.end()) {
+      return std::string(str, length->second);
+    }
+  }
+  return std::string(str);
+}
+
+extern "C" double string_to_number(char tag, double value);
+extern "C" void free_string(char tag, double value);
+extern "C" void* new_array();
+void split_string(const std::string& str, const std::string& sep, bool is_regex, std::vector<std::string>& out);
+extern char** environ;
+
+// CONVFMT or OFMT. A program that assigns either a number gets the default format.
+static const char* special_format(char tag, double value) {
+  union Value val;
+  val.float_value = value;
+  return tag == 1 || tag == 2 || tag == 3 ? val.str_value : "%.6g";
+}
+
+// Integral values no larger than this are printed as integers. Past 2^53 a double can't hold
+// every integer so those use the format like any other number.
+static const double MAX_EXACT_INTEGER = 9007199254740992.0;
+
+// A number as a string, integral values with %d and anything else with format
+static std::string format_number(double value, const char* format) {
+  if (value == trunc(value) && fabs(value) <= MAX_EXACT_INTEGER) {
+    return std::to_string((long long) value);
+  }
+  int needed = snprintf(nullptr, 0, format, value);
+  if (needed < 0) {
+    return std::string();
+  }
+  std::vector<char> buffer(needed + 1);
+  snprintf(buffer.data(), buffer.size(), format, value);
+  return std::string(buffer.data(), needed);
+}
+
+// Returns the string value of any awk value. Numbers are formatted with CONVFMT
+// unless they are integral.
+std::string to_std_string(char tag, double value) {
+  union Value val;
+  val.float_value = value;
+  if (tag == 4) {
+    return std::string();
+  }
+  if (tag != 0) {
+    return string_contents(val.str_value);
+  }
+  return format_number(value, special_format(special_CONVFMT_tag, special_CONVFMT_value));
+}
+
+// Compiles an ERE the first time it is seen and caches it for every later match.
+// An invalid regex is fatal.
+regex_t* get_regex(const std::string& source) {
+  auto existing = regex_cache.find(source);
+  if (existing != regex_cache.end()) {
+    return existing->second;
+  }
+  PRINT("\tcompiling regex %s\n", source.c_str());
+  regex_t* compiled = (regex_t*) malloc(sizeof(regex_t));
+  int result = regcomp(compiled, source.c_str(), REG_EXTENDED);
+  if (result != 0) {
+    char error[256];
+    regerror(result, compiled, error, sizeof(error));
+    fprintf(stderr, "llawk: invalid regex /%s/: %s\n", source.c_str(), error);
+    exit(2);
+  }
+  if (regex_cache.size() >= MAX_CACHED_REGEXES) {
+    // Dynamic regexes can come from input so don't let the cache grow forever
+    PRINT("\tregex cache full, clearing\n");
+    for (auto& entry : regex_cache) {
+      regfree(entry.second);
+      free(entry.second);
+    }
+    regex_cache.clear();
+  }
+  regex_cache[source] = compiled;
+  return compiled;
+}
+
+// Set by --non-decimal-data to let input and string conversions read hex, inf and nan like gawk
+static bool non_decimal_data = false;
+
+extern "C" void allow_non_decimal_data() {
+  non_decimal_data = true;
+}
+
+// Converts the longest prefix of str, after leading blanks, that is a decimal number with an
+// optional sign, fraction and exponent. Returns 0 and sets end to str when there is none.
+static double number_prefix(const char* str, const char** end) {
+  const char* start = str;
+  while (isspace((unsigned char) *start)) {
+    start++;
+  }
+  *end = str;
+  char* strtod_end;
+  if (non_decimal_data) {
+    double number = strtod(start, &strtod_end);
+    if (strtod_end != start) {
+      *end = strtod_end;
+    }
+    return number;
+  }
+  // strtod also accepts hex, inf and nan which POSIX awk doesn't
+  const char* digits = (*start == '+' || *start == '-') ? start + 1 : start;
+  if (!isdigit((unsigned char) digits[0]) && !(digits[0] == '.' && isdigit((unsigned char) digits[1]))) {
+    return 0;
+  }
+  if (digits[0] == '0' && (digits[1] == 'x' || digits[1] == 'X')) {
+    // Only the 0 of 0x is decimal
+    *end = digits + 1;
+    return 0;
+  }
+  double number = strtod(start, &strtod_end);
+  *end = strtod_end;
+  return number;
+}
+
+// True if the whole string is a number with optional leading and trailing blanks
+bool looks_numeric(const std::string& str) {
+  const char* end;
+  number_prefix(str.c_str(), &end);
+  if (end == str.c_str()) {
+    return false;
+  }
+  while (isspace((unsigned char) *end)) {
+    end++;
+  }
+  return *end == '\0';
+}
+
+// Input is a strnum when it looks numeric, otherwise a plain string. Returns the tag.
+char input_tag(const std::string& str) {
+  return looks_numeric(str) ? 3 : 1;
+}
+
 // Frees a string created by owned_string
 extern "C" void free_string(char tag, double value) {
   PRINT("Free string called tag:%d value:%g\n", tag, value);
-  if (tag == 1) {
+  if (owns_string(tag)) {
     union Value myVal;
     myVal.float_value = value;
+    if (!nul_string_lengths.empty()) {
+      nul_string_lengths.erase(myVal.str_value);
+    }
     free( (void*) myVal.str_value );
   } else {
     printf("\tllawk compiler bug: tried to free a non-string value!\n");
@@ -58,162 +285,1290 @@ extern "C" void add_file(void *path) {
   files.push_back(std::string(path_str));
 }
 
-// Called when done adding files;
+
+static void set_special_number(char& tag, double& value, double number) {
+  if (owns_string(tag)) {
+    free_string(tag, value);
+  }
+  tag = 0;
+  value = number;
+}
+
+static void increment_special(char& tag, double& value) {
+  set_special_number(tag, value, string_to_number(tag, value) + 1);
+}
+
+static void set_special_string(char& tag, double& value, const std::string& str) {
+  if (owns_string(tag)) {
+    free_string(tag, value);
+  }
+  union Value val;
+  val.str_value = owned_string(str);
+  tag = 1;
+  value = val.float_value;
+}
+
+// Stores an owned string in array[key]. Strings that look numeric are strnums.
+static void set_element(void* array, const std::string& key, const std::string& str) {
+  union Value val;
+  val.str_value = owned_string(str);
+  Cell cell;
+  cell.tag = input_tag(str);
+  cell.value = val.float_value;
+  (*(Array*) array)[key] = cell;
+}
+
+// Called when done adding files. Sets up the special variables. ARGV holds the files to read,
+// with no files the main input is stdin.
 extern "C" void init() {
   PRINT("Init called\n");
-  if (files.empty()) {
-    PRINT("\t files empty return");
-    return;
+  // String comparisons use the collation order of the user's locale
+  setlocale(LC_COLLATE, "");
+  set_special_string(special_SUBSEP_tag, special_SUBSEP_value, "\034");
+  set_special_string(special_FS_tag, special_FS_value, " ");
+  set_special_string(special_RS_tag, special_RS_value, "\n");
+  set_special_string(special_RT_tag, special_RT_value, "");
+  set_special_string(special_CONVFMT_tag, special_CONVFMT_value, "%.6g");
+  set_special_string(special_OFMT_tag, special_OFMT_value, "%.6g");
+  set_special_string(special_OFS_tag, special_OFS_value, " ");
+  set_special_string(special_FILENAME_tag, special_FILENAME_value, "");
+  set_special_number(special_RLENGTH_tag, special_RLENGTH_value, -1);
+
+  special_ARGV = new_array();
+  set_element(special_ARGV, "0", "awk");
+  for (size_t i = 0; i < files.size(); i++) {
+    set_element(special_ARGV, std::to_string(i + 1), files[files.size() - 1 - i]);
   }
-  std::string current = files.at(files.size() - 1);
-  files.pop_back();
-  PRINT("\tsetting current file %s\n", current.c_str());
-  current_file.open(current);
+  set_special_number(special_ARGC_tag, special_ARGC_value, (double) files.size() + 1);
+
+  special_ENVIRON = new_array();
+  for (char** env = environ; *env != nullptr; env++) {
+    const char* equals = strchr(*env, '=');
+    if (equals != nullptr) {
+      set_element(special_ENVIRON, std::string(*env, equals - *env), std::string(equals + 1));
+    }
+  }
+}
+
+// Opens the file name for reading, "-" and /dev/stdin are stdin. Returns null if it can't be opened.
+static FILE* open_read_file(const std::string& name) {
+  if (name == "-" || name == "/dev/stdin") {
+    return stdin;
+  }
+  return fopen(name.c_str(), "r");
+}
+
+// Closes a file opened by open_read_file and drops anything read ahead from it
+static void close_read_file(FILE* file) {
+  if (file != stdin) {
+    pending_input.erase(file);
+    fclose(file);
+  }
+}
+
+// Opens name for reading or returns the stream already open under that name. Returns null if it
+// can't be opened.
+static FILE* open_input(const std::string& name, bool is_command) {
+  auto existing = inputs.find(name);
+  if (existing != inputs.end()) {
+    return existing->second.file;
+  }
+  FILE* file;
+  if (is_command) {
+    // Anything already printed must come out before the command's output
+    fflush(stdout);
+    file = popen(name.c_str(), "r");
+  } else {
+    file = open_read_file(name);
+  }
+  if (file == nullptr) {
+    PRINT("\tfailed to open %s\n", name.c_str());
+    return nullptr;
+  }
+  inputs[name] = InputStream{file, is_command};
+  return file;
 }
 
+// Waits for a command opened with popen. Returns its exit status, or 256 plus the signal number
+// if it was killed.
+static int command_status(FILE* file) {
+  int status = pclose(file);
+  return WIFEXITED(status) ? WEXITSTATUS(status) : 256 + WTERMSIG(status);
+}
+
+// Closes an input opened by open_input. Returns the exit status of a command, 0 for a file and
+// -1 if nothing is open under that name.
+static int close_input(const std::string& name) {
+  auto existing = inputs.find(name);
+  if (existing == inputs.end()) {
+    return -1;
+  }
+  InputStream stream = existing->second;
+  inputs.erase(existing);
+  if (stream.is_command) {
+    pending_input.erase(stream.file);
+    return command_status(stream.file);
+  }
+  close_read_file(stream.file);
+  return 0;
+}
+
+// Reads a stream a character at a time, starting with the input left over from the last record
+// read from it. The leftover input is only looked up once per record.
+struct RecordReader {
+  FILE* file;
+  std::string pending;
+  size_t used;
+
+  bool has_pending() const {
+    return used < pending.length();
+  }
+
+  int get() {
+    if (has_pending()) {
+      return (unsigned char) pending[used++];
+    }
+    return getc(file);
+  }
+
+  // Puts str back in front of the input not read yet
+  void unread(const std::string& str) {
+    pending = str + pending.substr(used);
+    used = 0;
+  }
+};
+
+// A record ended by the single character separator. Once the leftover input is used up the rest
+// of the record is read from the stream with getdelim.
+static bool read_char_record(RecordReader& reader, char separator, std::string& line, std::string& terminator) {
+  bool read_any = false;
+  while (reader.has_pending()) {
+    int c = reader.get();
+    read_any = true;
+    if (c == (unsigned char) separator) {
+      terminator = std::string(1, separator);
+      return true;
+    }
+    line += (char) c;
+  }
+  static char* buffer = nullptr;
+  static size_t capacity = 0;
+  ssize_t length = getdelim(&buffer, &capacity, (unsigned char) separator, reader.file);
+  if (length <= 0) {
+    return read_any;
+  }
+  if (buffer[length - 1] == separator) {
+    line.append(buffer, length - 1);
+    terminator = std::string(1, separator);
+  } else {
+    line.append(buffer, length);
+  }
+  return true;
+}
+
+// RS = "". Records are separated by one or more blank lines, leading newlines are skipped.
+static bool read_paragraph(RecordReader& reader, std::string& line, std::string& terminator) {
+  int c;
+  while ((c = reader.get()) == '\n') {
+  }
+  while (c != EOF) {
+    if (c != '\n') {
+      line += (char) c;
+      c = reader.get();
+      continue;
+    }
+    std::string newlines(1, '\n');
+    while ((c = reader.get()) == '\n') {
+      newlines += '\n';
+    }
+    if (newlines.length() > 1 || c == EOF) {
+      terminator = newlines;
+      if (c != EOF) {
+        reader.unread(std::string(1, (char) c));
+      }
+      return true;
+    }
+    line += newlines;
+  }
+  return !line.empty();
+}
+
+// RS longer than one character is a regex. Input is read a line at a time until a match is found
+// that more input couldn't extend. What follows the match is kept for the next read.
+static bool read_regex_record(RecordReader& reader, const std::string& rs, std::string& line, std::string& terminator) {
+  regex_t* regex = get_regex(rs);
+  std::string buffer;
+  bool at_end = false;
+  while (true) {
+    size_t search = 0;
+    regmatch_t match;
+    while (search < buffer.length() &&
+           regexec(regex, buffer.c_str() + search, 1, &match, search > 0 ? REG_NOTBOL : 0) == 0) {
+      size_t start = search + match.rm_so;
+      size_t end = search + match.rm_eo;
+      if (start == end) {
+        // An empty match never ends a record
+        search = start + 1;
+        continue;
+      }
+      if (end == buffer.length() && !at_end) {
+        break;
+      }
+      line = buffer.substr(0, start);
+      terminator = buffer.substr(start, end - start);
+      reader.unread(buffer.substr(end));
+      return true;
+    }
+    if (at_end) {
+      line = buffer;
+      return !buffer.empty();
+    }
+    int c;
+    while ((c = reader.get()) != EOF) {
+      buffer += (char) c;
+      if (c == '\n') {
+        break;
+      }
+    }
+    at_end = c == EOF;
+  }
+}
+
+// True when RS is "", blank lines separate records and newline always separates fields
+static bool paragraph_mode() {
+  return to_std_string(special_RS_tag, special_RS_value).empty();
+}
+
+// Reads the next RS terminated record from file into line, without the RS, and sets RT to the
+// text that ended it. Returns false at the end of the file.
+static bool read_record(FILE* file, std::string& line) {
+  line.clear();
+  std::string rs = to_std_string(special_RS_tag, special_RS_value);
+  std::string terminator;
+  RecordReader reader{file, "", 0};
+  auto leftover = pending_input.find(file);
+  if (leftover != pending_input.end()) {
+    reader.pending.swap(leftover->second);
+    pending_input.erase(leftover);
+  }
+  bool read;
+  if (rs.empty()) {
+    read = read_paragraph(reader, line, terminator);
+  } else if (rs.length() == 1) {
+    read = read_char_record(reader, rs[0], line, terminator);
+  } else {
+    read = read_regex_record(reader, rs, line, terminator);
+  }
+  if (reader.has_pending()) {
+    pending_input[file] = reader.pending.substr(reader.used);
+  }
+  if (read) {
+    set_special_string(special_RT_tag, special_RT_value, terminator);
+  }
+  return read;
+}
+
+// The next file named in ARGV. Entries the program deleted or emptied are skipped. If ARGV names
+// no files the main input is stdin. Returns false when there are none left.
+static bool next_operand(std::string& name) {
+  Array* argv = (Array*) special_ARGV;
+  while (next_arg < string_to_number(special_ARGC_tag, special_ARGC_value)) {
+    auto entry = argv->find(std::to_string(next_arg++));
+    if (entry == argv->end()) {
+      continue;
+    }
+    name = to_std_string(entry->second.tag, entry->second.value);
+    if (!name.empty()) {
+      read_file_operand = true;
+      return true;
+    }
+  }
+  if (!read_file_operand && !read_stdin) {
+    read_stdin = true;
+    name = "-";
+    return true;
+  }
+  return false;
+}
+
+// Closes the current main input file and opens the next one. Returns 0 when there are no files left.
 int next_file() {
   PRINT("Next file called\n");
-  if (files.size() == 0) {
+  if (main_input != nullptr) {
+    close_read_file(main_input);
+    main_input = nullptr;
+  }
+  if (!next_operand(current_filename)) {
     PRINT("\tThere is no next file\n");
     return 0;
   }
-  std::string next_file = files.at(files.size() - 1);
-  PRINT("\tNext file is: %s\n", next_file.c_str());
-  current_file.close();
-  current_file.open(next_file);
-  files.pop_back();
+  PRINT("\tNext file is: %s\n", current_filename.c_str());
+  // FILENAME is empty when reading stdin because no files were named
+  set_special_string(special_FILENAME_tag, special_FILENAME_value, read_file_operand ? current_filename : "");
+  set_special_number(special_FNR_tag, special_FNR_value, 0);
+  main_input = open_read_file(current_filename);
+  if (main_input == nullptr) {
+    fprintf(stderr, "llawk: cannot open \"%s\" for reading\n", current_filename.c_str());
+  }
   return 1;
 }
 
-extern "C" double next_line() {
-  fields.clear();
-  PRINT("Next line called\n");
-  if (!std::getline(current_file, full_line, RS)) {
-    PRINT("\tGet line failed. Continuing as if current line empty\n");
-    full_line.clear();
-  }
-  PRINT("\tDone calling first getline %s\n", full_line.c_str());
-//  getline(current_file, full_line, RS);
-  if (full_line.length() == 0) {
-    PRINT("\tLine was empty trying next file\n");
-
-    int found = 0;
-    while (next_file()) {
-      PRINT("\tGetting line from next file\n");
-      getline(current_file, full_line, RS);
-      if (full_line.length() == 0) {
-        PRINT("\tLine was empty trying next file\n");
-        continue;
-      } else {
-        found = 1;
-        PRINT("\tGot something from this file\n");
-        break;
-      }
+// Reads the next record of the main input, moving through the files as each one runs out
+static bool read_main_record(std::string& line) {
+  while (true) {
+    if (main_input != nullptr && read_record(main_input, line)) {
+      increment_special(special_NR_tag, special_NR_value);
+      increment_special(special_FNR_tag, special_FNR_value);
+      return true;
     }
-    if (!found) {
-      PRINT("\tOut of files return false 0\n");
-      return 0.0;
+    if (!next_file()) {
+      return false;
     }
   }
-  size_t start = 0;
-  for (size_t i = 0; i < full_line.length(); i++) {
-    if (full_line[i] == FS) {
-      std::string substr = full_line.substr(start, i-start);
-      fields.push_back(substr);
-      PRINT("\tat %zu to %zu adding substr '%s'\n", start, i, substr.c_str());
-      start = i + 1;
+}
+
+static void update_nf() {
+  set_special_number(special_NF_tag, special_NF_value, (double) fields.size());
+}
+
+// Splits full_line into fields with the current FS. In paragraph mode newline separates fields
+// whatever FS is.
+void split_line() {
+  std::string fs = to_std_string(special_FS_tag, special_FS_value);
+  if (paragraph_mode() && fs != " " && !fs.empty()) {
+    if (fs.length() == 1) {
+      fs = fs == "]" ? "[]\n]" : "[\n" + fs + "]";
+    } else {
+      fs = "(" + fs + ")|\n";
     }
-    if (i == full_line.length() - 1) {
+    split_string(full_line, fs, true, fields);
+  } else {
+    split_string(full_line, fs, false, fields);
+  }
+  PRINT("\tsplit line into %zu fields\n", fields.size());
+  update_nf();
+}
 
-      std::string substr = full_line.substr(start, i-start+1);
-      fields.push_back(substr);
-      PRINT("\tadding trail off from %zu to %zu '%s'\n", start, i, substr.c_str());
+// Joins fields with OFS into full_line. Called after a field is assigned.
+void rebuild_line() {
+  std::string ofs = to_std_string(special_OFS_tag, special_OFS_value);
+  full_line.clear();
+  for (size_t i = 0; i < fields.size(); i++) {
+    if (i != 0) {
+      full_line += ofs;
     }
+    full_line += fields[i];
+  }
+  PRINT("\trebuilt line '%s'\n", full_line.c_str());
+}
+
+// Reads the next record of the main input into $0. Returns 0 once the input is used up, an empty
+// record is still a record. $0 keeps the last record after the input ends so END can see it.
+extern "C" double next_line() {
+  PRINT("Next line called\n");
+  std::string line;
+  if (!read_main_record(line)) {
+    PRINT("\tOut of input return false 0\n");
+    return 0.0;
   }
+  full_line = std::move(line);
+  split_line();
   PRINT("next line returns 1-true\n");
   return 1.0;
 }
 
-// Returns a pointer to a c_string that the caller now is responsible for
-// freeing or 0 if column is too large.
-extern "C" double column(char tag, double value) {
+// A record read by getline becomes $0 when the out pointers are null. Otherwise an owned copy is
+// written to them.
+static void store_getline(const std::string& line, char* out_tag, double* out_value) {
+  if (out_tag == nullptr) {
+    full_line = line;
+    split_line();
+    return;
+  }
+  union Value val;
+  val.str_value = owned_string(line);
+  *out_tag = input_tag(line);
+  *out_value = val.float_value;
+}
+
+// getline and getline var. Both read the next record of the main input and update NR and FNR.
+extern "C" double getline_main(char* out_tag, double* out_value) {
+  PRINT("getline_main called\n");
+  std::string line;
+  if (!read_main_record(line)) {
+    return 0.0;
+  }
+  store_getline(line, out_tag, out_value);
+  return 1.0;
+}
+
+// getline < file and getline var < file. Neither changes NR or FNR.
+extern "C" double getline_file(char tag, double value, char* out_tag, double* out_value) {
+  std::string name = to_std_string(tag, value);
+  PRINT("getline_file called %s\n", name.c_str());
+  FILE* file = open_input(name, false);
+  if (file == nullptr) {
+    return -1.0;
+  }
+  std::string line;
+  if (!read_record(file, line)) {
+    return 0.0;
+  }
+  store_getline(line, out_tag, out_value);
+  return 1.0;
+}
+
+// cmd | getline and cmd | getline var. Both update NR but not FNR.
+extern "C" double getline_command(char tag, double value, char* out_tag, double* out_value) {
+  std::string command = to_std_string(tag, value);
+  PRINT("getline_command called %s\n", command.c_str());
+  FILE* file = open_input(command, true);
+  if (file == nullptr) {
+    return -1.0;
+  }
+  std::string line;
+  if (!read_record(file, line)) {
+    return 0.0;
+  }
+  increment_special(special_NR_tag, special_NR_value);
+  store_getline(line, out_tag, out_value);
+  return 1.0;
+}
+
+// nextfile statement. Moves on to the next file, the following next_line reads from it.
+extern "C" void nextfile() {
+  PRINT("nextfile called\n");
+  if (main_input != nullptr) {
+    close_read_file(main_input);
+    main_input = nullptr;
+  }
+}
+
+// A function called from a BEGIN or END action ran next or nextfile. There is no record to skip.
+extern "C" void next_outside_records() {
+  fprintf(stderr, "llawk: next used in a BEGIN or END action\n");
+  exit(2);
+}
+
+// Opens name for output or returns the stream already open under that name. mode is 0 for
+// `> file`, which truncates the file the first time it is opened, 1 for `>> file` and 2 for
+// `| cmd`. Failing to open a file or command is fatal.
+extern "C" void* output_stream(char tag, double value, int mode) {
+  std::string name = to_std_string(tag, value);
+  PRINT("output_stream called %s mode %d\n", name.c_str(), mode);
+  auto existing = outputs.find(name);
+  if (existing != outputs.end()) {
+    return existing->second.file;
+  }
+  FILE* file;
+  if (mode == 2) {
+    // Anything already printed must come out before the command's output
+    fflush(stdout);
+    file = popen(name.c_str(), "w");
+  } else if (name == "-" || name == "/dev/stdout") {
+    file = stdout;
+  } else if (name == "/dev/stderr") {
+    file = stderr;
+  } else {
+    file = fopen(name.c_str(), mode == 0 ? "w" : "a");
+  }
+  if (file == nullptr) {
+    fprintf(stderr, "awk: can't redirect to %s\n", name.c_str());
+    exit(2);
+  }
+  outputs[name] = OutputStream{file, mode == 2};
+  return file;
+}
+
+// print and printf pass a null stream when they write to stdout
+static FILE* stream_or_stdout(void* stream) {
+  return stream == nullptr ? stdout : (FILE*) stream;
+}
+
+// Closes an output opened by output_stream. Returns the exit status of a command, 0 for a file
+// and -1 if nothing is open under that name.
+static int close_output(const std::string& name) {
+  auto existing = outputs.find(name);
+  if (existing == outputs.end()) {
+    return -1;
+  }
+  OutputStream stream = existing->second;
+  outputs.erase(existing);
+  if (stream.is_command) {
+    fflush(stdout);
+    return command_status(stream.file);
+  }
+  if (stream.file == stdout || stream.file == stderr) {
+    fflush(stream.file);
+    return 0;
+  }
+  return fclose(stream.file) == 0 ? 0 : -1;
+}
+
+// close(name). Closes the output and input open under name, the result is the output's if both
+// were open.
+extern "C" double close_stream(char tag, double value) {
+  std::string name = to_std_string(tag, value);
+  PRINT("close_stream called %s\n", name.c_str());
+  int output_result = close_output(name);
+  int input_result = close_input(name);
+  return output_result != -1 ? output_result : input_result;
+}
+
+// fflush(name). Returns -1 if nothing is open for output under name.
+extern "C" double flush_output(char tag, double value) {
+  std::string name = to_std_string(tag, value);
+  PRINT("flush_output called %s\n", name.c_str());
+  if (name.empty()) {
+    fflush(nullptr);
+    return 0.0;
+  }
+  auto existing = outputs.find(name);
+  if (existing == outputs.end()) {
+    return -1.0;
+  }
+  return fflush(existing->second.file) == 0 ? 0.0 : -1.0;
+}
+
+// fflush() with no argument flushes stdout and every open output
+extern "C" double flush_all() {
+  PRINT("flush_all called\n");
+  return fflush(nullptr) == 0 ? 0.0 : -1.0;
+}
+
+// Closes every output so commands like `print | "sort"` finish before the program exits
+extern "C" void close_all_streams() {
+  PRINT("close_all_streams called\n");
+  while (!outputs.empty()) {
+    close_output(outputs.begin()->first);
+  }
+}
+
+// exit statement. Called after the END blocks have run. exit flushes any buffered output.
+extern "C" void exit_program(long long status) {
+  PRINT("exit called with %lld\n", status);
+  close_all_streams();
+  exit((int) status);
+}
+
+// The field number for $(tag, value). Fractions are truncated, negative numbers and NaN are fatal.
+static size_t field_index(char tag, double value) {
+  double index = string_to_number(tag, value);
+  if (index < 0 || isnan(index)) {
+    fprintf(stderr, "llawk: attempt to access field $%g\n", index);
+    exit(2);
+  }
+  return (size_t) index;
+}
+
+// Writes an owned copy of the column to the out pointers. Columns past the end are empty.
+extern "C" void column(char tag, double value, char* out_tag, double* out_value) {
   PRINT("column call tag %d value %g\n", tag, value);
   union Value val;
-  if (tag == 0) {
-    if (value == 0) {
-      PRINT("\tcolumn == 0 return full line\n");
-      val.str_value= owned_string(full_line);
-      return val.float_value;
-    }
-    if (value - 1 >= fields.size()) {
-      PRINT("\tcolumn too large ret empty string\n");
-      std::string empty = "";
-      val.str_value= owned_string(empty);
-      return val.float_value;
-    }
-    val.str_value= owned_string(fields.at(value-1));
-    PRINT("\tcolumn normal return fields[col-1] %s\n", fields.at(value-1).c_str());
-    return val.float_value;
+  std::string result;
+  size_t idx = field_index(tag, value);
+  if (idx == 0) {
+    PRINT("\tcolumn == 0 return full line\n");
+    result = full_line;
+  } else if (idx > fields.size()) {
+    PRINT("\tcolumn too large ret empty string\n");
   } else {
-    PRINT("\tCannot get column from tag %d returning $0\n", tag);
-    val.str_value= owned_string(full_line);
-    return val.float_value;
+    result = fields[idx - 1];
+    PRINT("\tcolumn normal return fields[col-1] %s\n", result.c_str());
+  }
+  val.str_value = owned_string(result);
+  *out_tag = input_tag(result);
+  *out_value = val.float_value;
+}
+
+// Assigns the string value of (new_tag, new_value) to $index. Assigning $0 re-splits the fields,
+// assigning any other field rebuilds $0 with OFS. Fields past the end are created empty.
+// Does not free the new value.
+extern "C" void set_column(char tag, double value, char new_tag, double new_value) {
+  PRINT("set_column call tag %d value %g\n", tag, value);
+  size_t idx = field_index(tag, value);
+  std::string str = to_std_string(new_tag, new_value);
+  if (idx == 0) {
+    full_line = str;
+    split_line();
+    return;
+  }
+  if (idx > fields.size()) {
+    fields.resize(idx);
+    update_nf();
+  }
+  fields[idx - 1] = str;
+  rebuild_line();
+}
+
+// Called after the program assigns NF. Drops fields or adds empty ones to match and rebuilds $0
+// with OFS.
+extern "C" void assign_nf() {
+  double nf = string_to_number(special_NF_tag, special_NF_value);
+  PRINT("assign_nf called with %g\n", nf);
+  if (nf < 0 || isnan(nf)) {
+    fprintf(stderr, "llawk: NF set to invalid value %g\n", nf);
+    exit(2);
   }
+  fields.resize((size_t) nf);
+  update_nf();
+  rebuild_line();
 }
 
-extern "C" void print_value(char tag, double value) {
-  // Is it UB? Yes. Is it easy? Yes;
+// Returns a copy of owned strings so two variables never share one allocation.
+// Anything else is returned as is.
+extern "C" double copy_if_string(char tag, double value) {
+  if (!owns_string(tag)) {
+    return value;
+  }
   union Value val;
   val.float_value = value;
-  PRINT("Print value called tag %c value %g\n", tag, val.float_value);
+  val.str_value = owned_string(string_contents(val.str_value));
+  return val.float_value;
+}
+
+// print of a single value. Numbers are formatted with OFMT unless they are integral.
+extern "C" void print_value(void* stream, char tag, double value) {
+  FILE* out = stream_or_stdout(stream);
+  PRINT("Print value called tag %d value %g\n", tag, value);
+  std::string str = tag == 0 ? format_number(value, special_format(special_OFMT_tag, special_OFMT_value)) : to_std_string(tag, value);
+  fprintf(out, "%s\n", str.c_str());
+}
+
+// The number at the start of a string, eg. " 12abc" is 12 and "1e3x" is 1000. Strings that don't
+// start with a number are 0.
+extern "C" double string_to_number(char tag, double value) {
+  union Value val;
+  val.float_value = value;
+  PRINT("string_to_number called tag %d value %g\n", tag, value);
   if (tag == 0) {
+    return value;
+  }
+  if (tag == 4) {
+    return 0;
+  }
+  const char* end;
+  return number_prefix(val.str_value, &end);
+}
 
-    if (ceilf(value) == value) {
-      int64_t int_value = static_cast<int>(value);
-      PRINT("\tceilf(value) == value printing int lld\n");
-      printf("%lld\n", int_value);
+// Returns an owned string, numbers are converted with CONVFMT
+extern "C" double number_to_string(char tag, double value) {
+  PRINT("number_to_string called tag %d value %g\n", tag, value);
+  union Value val;
+  val.str_value = owned_string(to_std_string(tag, value));
+  return val.float_value;
+}
+
+// snprintf into a std::string of the right size
+template <typename T>
+std::string format_one(const std::string& spec, T value) {
+  int needed = snprintf(nullptr, 0, spec.c_str(), value);
+  if (needed < 0) {
+    return std::string();
+  }
+  std::vector<char> buffer(needed + 1);
+  snprintf(buffer.data(), buffer.size(), spec.c_str(), value);
+  return std::string(buffer.data(), needed);
+}
+
+template <typename T>
+static double apply_comparison(int op, T left, T right) {
+  switch (op) {
+    case 0: return left < right;
+    case 1: return left <= right;
+    case 2: return left == right;
+    case 3: return left != right;
+    case 4: return left >= right;
+    default: return left > right;
+  }
+}
+
+// left op right where op is 0 for <, 1 <=, 2 ==, 3 !=, 4 >= and 5 >. Numbers, strnums and
+// uninitialized values compare as numbers when both sides are one of them, anything else compares
+// as strings in the locale's collation order.
+extern "C" double compare_values(char left_tag, double left, char right_tag, double right, int op) {
+  if (compares_as_number(left_tag) && compares_as_number(right_tag)) {
+    return apply_comparison(op, string_to_number(left_tag, left), string_to_number(right_tag, right));
+  }
+  std::string left_str = to_std_string(left_tag, left);
+  std::string right_str = to_std_string(right_tag, right);
+  PRINT("compare_values comparing strings '%s' '%s' op %d\n", left_str.c_str(), right_str.c_str(), op);
+  return apply_comparison(op, strcoll(left_str.c_str(), right_str.c_str()), 0);
+}
+
+// Formats args per awk's printf rules. Conversions are %d %i %o %x %X %u %c %s %e %E %f %F
+// %g %G and %% with the flags -+ #0, a width, and a precision. Either of the last two may
+// be * to take it from the next argument. Missing arguments are treated as empty strings.
+std::string format_values(const std::string& fmt, char* tags, double* values, int count) {
+  std::string result;
+  int next_arg = 0;
+  char empty_tag = 2;
+  double empty_value;
+  union Value empty;
+  empty.str_value = empty_string;
+  empty_value = empty.float_value;
+  auto arg = [&](char* tag, double* value) {
+    if (next_arg < count) {
+      *tag = tags[next_arg];
+      *value = values[next_arg];
     } else {
-      PRINT("\tceilf(value) != value printing float g\n");
-      printf("%g\n", val.float_value);
+      *tag = empty_tag;
+      *value = empty_value;
+    }
+    next_arg++;
+  };
+
+  size_t i = 0;
+  while (i < fmt.length()) {
+    if (fmt[i] != '%') {
+      result += fmt[i++];
+      continue;
+    }
+    size_t start = i++;
+    if (i < fmt.length() && fmt[i] == '%') {
+      result += '%';
+      i++;
+      continue;
+    }
+    std::string flags;
+    while (i < fmt.length() && strchr("-+ #0", fmt[i]) != nullptr) {
+      flags += fmt[i++];
+    }
+    std::string width;
+    if (i < fmt.length() && fmt[i] == '*') {
+      char tag; double value;
+      arg(&tag, &value);
+      long long w = (long long) string_to_number(tag, value);
+      if (w < 0) {
+        flags += '-';
+        w = -w;
+      }
+      width = std::to_string(w);
+      i++;
+    } else {
+      while (i < fmt.length() && isdigit((unsigned char) fmt[i])) {
+        width += fmt[i++];
+      }
+    }
+    std::string precision;
+    bool has_precision = false;
+    if (i < fmt.length() && fmt[i] == '.') {
+      has_precision = true;
+      i++;
+      if (i < fmt.length() && fmt[i] == '*') {
+        char tag; double value;
+        arg(&tag, &value);
+        long long p = (long long) string_to_number(tag, value);
+        // A negative precision is taken as if it were omitted
+        if (p < 0) {
+          has_precision = false;
+        } else {
+          precision = std::to_string(p);
+        }
+        i++;
+      } else {
+        while (i < fmt.length() && isdigit((unsigned char) fmt[i])) {
+          precision += fmt[i++];
+        }
+      }
+    }
+    // Length modifiers mean nothing in awk
+    while (i < fmt.length() && strchr("hlLqjzt", fmt[i]) != nullptr) {
+      i++;
+    }
+    if (i >= fmt.length()) {
+      // Incomplete specifier, print it as is
+      result += fmt.substr(start);
+      break;
+    }
+    char conversion = fmt[i++];
+    std::string spec = "%" + flags + width + (has_precision ? "." + precision : "");
+    char tag; double value;
+    switch (conversion) {
+      case 'd':
+      case 'i': {
+        arg(&tag, &value);
+        double number = string_to_number(tag, value);
+        if (isnan(number) || isinf(number) || fabs(number) >= 9223372036854775808.0) {
+          // Too big for a long long, print the integral part of the double instead
+          result += format_one("%" + flags + width + ".0f", trunc(number));
+        } else {
+          result += format_one(spec + "lld", (long long) number);
+        }
+        break;
+      }
+      case 'o':
+      case 'x':
+      case 'X':
+      case 'u': {
+        arg(&tag, &value);
+        double number = string_to_number(tag, value);
+        unsigned long long integer = number < 0 ? (unsigned long long) (long long) number : (unsigned long long) number;
+        result += format_one(spec + "ll" + conversion, integer);
+        break;
+      }
+      case 'e':
+      case 'E':
+      case 'f':
+      case 'F':
+      case 'g':
+      case 'G': {
+        arg(&tag, &value);
+        result += format_one(spec + conversion, string_to_number(tag, value));
+        break;
+      }
+      case 'c': {
+        // A number prints the character with that code, a string prints its first character
+        arg(&tag, &value);
+        std::string character;
+        if (tag == 0 || tag == 3) {
+          character = std::string(1, (char) (int) string_to_number(tag, value));
+        } else {
+          std::string str = to_std_string(tag, value);
+          character = str.substr(0, 1);
+        }
+        // Padded here rather than with %s, which would stop at the NUL %c of 0 prints
+        size_t pad_to = width.empty() ? 0 : std::stoul(width);
+        std::string padding(pad_to > character.length() ? pad_to - character.length() : 0, ' ');
+        result += flags.find('-') == std::string::npos ? padding + character : character + padding;
+        break;
+      }
+      case 's': {
+        arg(&tag, &value);
+        std::string str = to_std_string(tag, value);
+        result += format_one(spec + "s", str.c_str());
+        break;
+      }
+      default:
+        // Unknown conversion, print it as is
+        result += fmt.substr(start, i - start);
+        break;
     }
-    PRINT("\tTag is == 0 DONE\n");
-  } else if (tag == 2 || tag == 1) {
-    printf("%s\n", val.str_value);
   }
+  return result;
 }
 
-extern "C" double string_to_number(char tag, double value) {
-  // TODO: Strings that can’t be interpreted as valid numbers convert to zero.
+extern "C" void printf_values(void* stream, char fmt_tag, double fmt, char* tags, double* values, int count) {
+  PRINT("printf_values called count %d\n", count);
+  std::string result = format_values(to_std_string(fmt_tag, fmt), tags, values, count);
+  fwrite(result.data(), 1, result.length(), stream_or_stdout(stream));
+}
+
+// Returns an owned string
+extern "C" double sprintf_values(char fmt_tag, double fmt, char* tags, double* values, int count) {
+  PRINT("sprintf_values called count %d\n", count);
+  union Value val;
+  val.str_value = owned_string(format_values(to_std_string(fmt_tag, fmt), tags, values, count));
+  return val.float_value;
+}
+
+// Truthiness of a string value. A strnum is true when its number is non-zero, any other string
+// when it is non-empty.
+extern "C" double string_truthy(char tag, double value) {
   union Value val;
   val.float_value = value;
-  PRINT("string_to_number called tag %d value %g\n", tag, value);
+  if (tag == 4) {
+    return 0.0;
+  }
+  if (tag == 3) {
+    return string_to_number(tag, value) != 0 ? 1.0 : 0.0;
+  }
+  return val.str_value[0] != '\0' ? 1.0 : 0.0;
+}
 
-  // TODO: This is UB if the string is not representable as a double.
-  return atof(val.str_value);
+// Joins the string values of each value for `a b c`. Numbers are converted with CONVFMT.
+// Returns an owned string.
+extern "C" double concat_values(char* tags, double* values, int count) {
+  PRINT("concat_values called count %d\n", count);
+  std::string result;
+  for (int i = 0; i < count; i++) {
+    result += to_std_string(tags[i], values[i]);
+  }
+  union Value val;
+  val.str_value = owned_string(result);
+  return val.float_value;
 }
 
-extern "C" double number_to_string(char tag, double value) {
+// Returns 1 if the string value of (tag, value) matches the regex (regex_tag, regex_value)
+// and 0 otherwise. Neither value is freed.
+extern "C" double match_regex(char tag, double value, char regex_tag, double regex_value) {
+  PRINT("match_regex called tag %d regex_tag %d\n", tag, regex_tag);
+  std::string subject = to_std_string(tag, value);
+  regex_t* regex = get_regex(to_std_string(regex_tag, regex_value));
+  int result = regexec(regex, subject.c_str(), 0, nullptr, 0);
+  return result == 0 ? 1.0 : 0.0;
+}
+
+
+struct ArrayIterator {
+  std::vector<std::string> keys;
+  size_t next;
+};
+
+// Elements that are referenced before being assigned are uninitialized values
+Cell uninitialized_cell() {
+  Cell cell;
+  cell.tag = 4;
+  cell.value = 0;
+  return cell;
+}
+
+void free_cell(Cell& cell) {
+  if (owns_string(cell.tag)) {
+    free_string(cell.tag, cell.value);
+  }
+}
+
+extern "C" void* new_array() {
+  PRINT("new_array called\n");
+  return new Array();
+}
+
+// Writes a copy of array[key] to the out pointers, the caller owns the copy.
+// Like awk, referencing a missing element creates it.
+extern "C" void array_get(void* array, char key_tag, double key_value, char* out_tag, double* out_value) {
+  Array* arr = (Array*) array;
+  std::string key = to_std_string(key_tag, key_value);
+  PRINT("array_get called key %s\n", key.c_str());
+  auto existing = arr->find(key);
+  if (existing == arr->end()) {
+    existing = arr->emplace(key, uninitialized_cell()).first;
+  }
+  *out_tag = existing->second.tag;
+  *out_value = copy_if_string(existing->second.tag, existing->second.value);
+}
+
+// Stores a copy of (tag, value) in array[key]. Does not free the value passed in.
+extern "C" void array_set(void* array, char key_tag, double key_value, char tag, double value) {
+  Array* arr = (Array*) array;
+  std::string key = to_std_string(key_tag, key_value);
+  PRINT("array_set called key %s\n", key.c_str());
+  auto existing = arr->find(key);
+  if (existing != arr->end()) {
+    free_cell(existing->second);
+  }
+  Cell cell;
+  cell.tag = tag;
+  cell.value = copy_if_string(tag, value);
+  (*arr)[key] = cell;
+}
+
+// Returns 1 if key is in the array, 0 otherwise. Never creates the element.
+extern "C" double array_in(void* array, char key_tag, double key_value) {
+  Array* arr = (Array*) array;
+  std::string key = to_std_string(key_tag, key_value);
+  PRINT("array_in called key %s\n", key.c_str());
+  return arr->find(key) != arr->end() ? 1.0 : 0.0;
+}
+
+extern "C" void array_delete(void* array, char key_tag, double key_value) {
+  Array* arr = (Array*) array;
+  std::string key = to_std_string(key_tag, key_value);
+  PRINT("array_delete called key %s\n", key.c_str());
+  auto existing = arr->find(key);
+  if (existing != arr->end()) {
+    free_cell(existing->second);
+    arr->erase(existing);
+  }
+}
+
+extern "C" void array_clear(void* array) {
+  Array* arr = (Array*) array;
+  PRINT("array_clear called\n");
+  for (auto& entry : *arr) {
+    free_cell(entry.second);
+  }
+  arr->clear();
+}
+
+// Frees an array created for a local that the caller didn't pass to a function
+extern "C" void free_array(void* array) {
+  PRINT("free_array called\n");
+  array_clear(array);
+  delete (Array*) array;
+}
+
+// Joins two subscripts with SUBSEP for array[a, b]. Returns an owned string.
+extern "C" double subsep_join(char left_tag, double left, char right_tag, double right) {
   union Value val;
-  val.float_value = value;
-  PRINT("number_to_string called tag %d value %g\n", tag, value);
+  std::string subsep = to_std_string(special_SUBSEP_tag, special_SUBSEP_value);
+  val.str_value = owned_string(to_std_string(left_tag, left) + subsep + to_std_string(right_tag, right));
+  return val.float_value;
+}
+
+// Snapshots the keys of an array for `for (k in array)`.
+extern "C" void* array_iter(void* array) {
+  Array* arr = (Array*) array;
+  PRINT("array_iter called\n");
+  ArrayIterator* iter = new ArrayIterator();
+  iter->next = 0;
+  for (auto& entry : *arr) {
+    iter->keys.push_back(entry.first);
+  }
+  return iter;
+}
+
+extern "C" double array_iter_has_next(void* iterator) {
+  ArrayIterator* iter = (ArrayIterator*) iterator;
+  return iter->next < iter->keys.size() ? 1.0 : 0.0;
+}
+
+// Returns the next key as an owned string.
+extern "C" double array_iter_next(void* iterator) {
+  ArrayIterator* iter = (ArrayIterator*) iterator;
+  union Value val;
+  val.str_value = owned_string(iter->keys.at(iter->next));
+  iter->next += 1;
+  return val.float_value;
+}
+
+extern "C" void array_iter_free(void* iterator) {
+  delete (ArrayIterator*) iterator;
+}
+
+// Splits str the way split() and field splitting do. A sep of " " splits on runs of blanks,
+// tabs and newlines ignoring leading and trailing ones. Any other single character is literal
+// and anything longer, or a regex literal, is an ERE. An empty sep splits into characters.
+void split_string(const std::string& str, const std::string& sep, bool is_regex, std::vector<std::string>& out) {
+  out.clear();
+  if (str.empty()) {
+    return;
+  }
+  if (!is_regex && sep == " ") {
+    size_t i = 0;
+    while (true) {
+      while (i < str.length() && (str[i] == ' ' || str[i] == '\t' || str[i] == '\n')) {
+        i++;
+      }
+      if (i == str.length()) {
+        return;
+      }
+      size_t start = i;
+      while (i < str.length() && str[i] != ' ' && str[i] != '\t' && str[i] != '\n') {
+        i++;
+      }
+      out.push_back(str.substr(start, i - start));
+    }
+  }
+  if (sep.empty()) {
+    for (char c : str) {
+      out.push_back(std::string(1, c));
+    }
+    return;
+  }
+  if (!is_regex && sep.length() == 1) {
+    size_t start = 0;
+    size_t found;
+    while ((found = str.find(sep[0], start)) != std::string::npos) {
+      out.push_back(str.substr(start, found - start));
+      start = found + 1;
+    }
+    out.push_back(str.substr(start));
+    return;
+  }
+  regex_t* regex = get_regex(sep);
+  size_t start = 0;
+  size_t search = 0;
+  regmatch_t match;
+  while (search <= str.length() &&
+         regexec(regex, str.c_str() + search, 1, &match, search > 0 ? REG_NOTBOL : 0) == 0) {
+    if (match.rm_eo == match.rm_so) {
+      // An empty match never separates anything
+      search += match.rm_so + 1;
+      continue;
+    }
+    out.push_back(str.substr(start, search + match.rm_so - start));
+    start = search + match.rm_eo;
+    search = start;
+  }
+  out.push_back(str.substr(start));
+}
 
-  char* result = (char*) malloc(64);
-  int bytes_needed_init = snprintf(result, 64, &CONVFMT[0], val.float_value);
-  if (bytes_needed_init < 0) {
-    printf("FAILURE converting number to string %g tag(%d)\n", value, tag);
+// split(str, array, sep). mode is 0 to split with FS, 1 for a string sep and 2 for a regex
+// literal. Clears the array and fills it from index 1. Returns the number of elements.
+extern "C" double split_values(char tag, double value, void* array, char sep_tag, double sep_value, int mode) {
+  std::string str = to_std_string(tag, value);
+  std::string sep = mode == 0 ? to_std_string(special_FS_tag, special_FS_value) : to_std_string(sep_tag, sep_value);
+  PRINT("split_values called '%s' sep '%s' mode %d\n", str.c_str(), sep.c_str(), mode);
+  std::vector<std::string> parts;
+  split_string(str, sep, mode == 2, parts);
+  array_clear(array);
+  for (size_t i = 0; i < parts.size(); i++) {
+    set_element(array, std::to_string(i + 1), parts[i]);
   }
-  if (bytes_needed_init > 64) {
-    free(result);
-    result = (char*) malloc(bytes_needed_init);
-    int bytes_needed_realloc = snprintf(result, 64, &CONVFMT[0], val.float_value);
-    if (bytes_needed_realloc > bytes_needed_init) {
-      printf("FAILURE converting number to string %g tag(%d)\n", value, tag);
+  return (double) parts.size();
+}
+
+extern "C" double length_value(char tag, double value) {
+  return (double) to_std_string(tag, value).length();
+}
+
+// substr(str, start, length). Positions are 1 based and rounded, the part of the range that
+// falls outside the string is dropped. length is infinite when substr has two arguments.
+// Returns an owned string.
+extern "C" double substr_value(char tag, double value, char start_tag, double start_value, char length_tag, double length_value) {
+  std::string str = to_std_string(tag, value);
+  double start = nearbyint(string_to_number(start_tag, start_value));
+  double length = string_to_number(length_tag, length_value);
+  double end = isinf(length) ? length : start + nearbyint(length);
+  if (isnan(start) || isnan(end)) {
+    start = end = 1;
+  }
+  start = fmax(start, 1);
+  end = fmin(end, (double) str.length() + 1);
+  union Value val;
+  if (end <= start) {
+    val.str_value = owned_string("");
+  } else {
+    val.str_value = owned_string(str.substr((size_t) start - 1, (size_t) (end - start)));
+  }
+  return val.float_value;
+}
+
+// index(str, find). The 1 based position of find in str, 0 if it isn't there.
+extern "C" double index_of(char tag, double value, char find_tag, double find_value) {
+  size_t found = to_std_string(tag, value).find(to_std_string(find_tag, find_value));
+  return found == std::string::npos ? 0.0 : (double) (found + 1);
+}
+
+// Appends the replacement for one sub/gsub match. & is the matched text, \& is a literal &
+// and \\ a literal backslash. Any other backslash is kept.
+static void append_replacement(std::string& result, const std::string& replacement, const char* matched, size_t matched_length) {
+  for (size_t i = 0; i < replacement.length(); i++) {
+    char c = replacement[i];
+    if (c == '\\' && i + 1 < replacement.length() && (replacement[i + 1] == '&' || replacement[i + 1] == '\\')) {
+      result += replacement[++i];
+    } else if (c == '&') {
+      result.append(matched, matched_length);
+    } else {
+      result += c;
     }
   }
-  val.str_value = result;
+}
+
+// sub and gsub. Replaces the first (or with global every) match of regex in the target. When
+// anything was replaced the new owned string is written to the out pointers. Returns the
+// number of replacements.
+extern "C" double substitute(char regex_tag, double regex_value, char repl_tag, double repl_value,
+                             char tag, double value, int global, char* out_tag, double* out_value) {
+  regex_t* regex = get_regex(to_std_string(regex_tag, regex_value));
+  std::string replacement = to_std_string(repl_tag, repl_value);
+  std::string target = to_std_string(tag, value);
+  PRINT("substitute called target '%s' replacement '%s' global %d\n", target.c_str(), replacement.c_str(), global);
+  std::string result;
+  size_t pos = 0;
+  // Where the last non-empty match ended. An empty match right there is not a match, so
+  // gsub(/x*/, "-", "axb") gives "-a-b-"
+  long last_end = -1;
+  int count = 0;
+  regmatch_t match;
+  while (pos <= target.length() &&
+         regexec(regex, target.c_str() + pos, 1, &match, pos > 0 ? REG_NOTBOL : 0) == 0) {
+    size_t start = pos + match.rm_so;
+    size_t end = pos + match.rm_eo;
+    if (start == end && (long) start == last_end) {
+      if (start == target.length()) {
+        break;
+      }
+      result.append(target, pos, start - pos + 1);
+      pos = start + 1;
+      continue;
+    }
+    result.append(target, pos, start - pos);
+    append_replacement(result, replacement, target.c_str() + start, end - start);
+    count++;
+    if (start == end) {
+      // Step over a character so an empty match can't repeat
+      if (end < target.length()) {
+        result += target[end];
+      }
+      pos = end + 1;
+    } else {
+      last_end = (long) end;
+      pos = end;
+    }
+    if (!global) {
+      break;
+    }
+  }
+  if (count == 0) {
+    return 0.0;
+  }
+  if (pos < target.length()) {
+    result.append(target, pos, std::string::npos);
+  }
+  union Value val;
+  val.str_value = owned_string(result);
+  *out_tag = 1;
+  *out_value = val.float_value;
+  return (double) count;
+}
+
+// match(str, regex). Sets RSTART to the 1 based start of the leftmost longest match, 0 if there
+// is none, and RLENGTH to its length, -1 if none. Returns RSTART.
+extern "C" double match_string(char tag, double value, char regex_tag, double regex_value) {
+  std::string str = to_std_string(tag, value);
+  regex_t* regex = get_regex(to_std_string(regex_tag, regex_value));
+  regmatch_t match;
+  double start = 0;
+  double length = -1;
+  if (regexec(regex, str.c_str(), 1, &match, 0) == 0) {
+    start = (double) (match.rm_so + 1);
+    length = (double) (match.rm_eo - match.rm_so);
+  }
+  set_special_number(special_RSTART_tag, special_RSTART_value, start);
+  set_special_number(special_RLENGTH_tag, special_RLENGTH_value, length);
+  return start;
+}
+
+// Returns an owned copy of the string with every ASCII letter lower (or upper) cased
+static double convert_case(char tag, double value, int (*convert)(int)) {
+  std::string str = to_std_string(tag, value);
+  for (char& c : str) {
+    c = (char) convert((unsigned char) c);
+  }
+  union Value val;
+  val.str_value = owned_string(str);
   return val.float_value;
 }
 
+extern "C" double to_lower(char tag, double value) {
+  return convert_case(tag, value, tolower);
+}
+
+extern "C" double to_upper(char tag, double value) {
+  return convert_case(tag, value, toupper);
+}
+
+// rand() and srand(). The generator starts from seed 0 so runs are reproducible until srand is
+// called with a new seed.
+static double rand_seed = 0;
+static std::mt19937_64 rand_generator(0);
+
+// A double in [0, 1) using the top 53 bits of the generator's output
+extern "C" double rand_value() {
+  return (double) (rand_generator() >> 11) * 0x1.0p-53;
+}
+
+static double reseed(double seed) {
+  double previous = rand_seed;
+  rand_seed = trunc(seed);
+  rand_generator.seed((unsigned long long) (long long) rand_seed);
+  return previous;
+}
+
+// srand(seed). Returns the previous seed.
+extern "C" double srand_value(char tag, double value) {
+  PRINT("srand_value called\n");
+  return reseed(string_to_number(tag, value));
+}
+
+// srand() with no argument seeds from the time of day. Returns the previous seed.
+extern "C" double srand_time() {
+  PRINT("srand_time called\n");
+  return reseed((double) time(nullptr));
+}
+
 // something llvm will probably not optimize out. Handy at times to see full IR.
 extern "C" double get_float() {
   return 2.2;
@@ -1,4 +1,4 @@
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 #[derive(Debug, PartialEq)]
 pub struct AwkArgs {
@@ -6,9 +6,14 @@ pub struct AwkArgs {
     pub program: ProgramType,
     pub files: Vec<String>,
     pub save_executable: Option<PathBuf>,
+    // -F sepstring, before escape sequences are processed
+    pub field_separator: Option<String>,
+    // Like gawk, convert strings with hex, inf and nan to numbers
+    pub non_decimal_data: bool,
 }
 
 #[derive(Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ProgramType {
     CLI(String),
     File(String),
@@ -29,10 +34,12 @@ impl ProgramType {
 }
 
 fn print_help() {
-    eprintln!("Usage: llawk [--dump] [--save path] -f progfile file ...");
-    eprintln!("Usage: llawk [--dump] [--save path] 'program' file ...");
+    eprintln!("Usage: llawk [--dump] [--save path] [-F sepstring] -f progfile file ...");
+    eprintln!("Usage: llawk [--dump] [--save path] [-F sepstring] 'program' file ...");
     eprintln!("--dump: Dump the AST after parsing");
     eprintln!("--save file_path: Save the executable to the given path");
+    eprintln!("-F sepstring: Use sepstring as the input field separator FS");
+    eprintln!("--non-decimal-data: Accept hex, inf and nan when converting strings to numbers, like gawk");
 }
 
 impl AwkArgs {
@@ -41,6 +48,8 @@ impl AwkArgs {
         let mut program: Option<ProgramType> = None;
         let mut files: Vec<String> = vec![];
         let mut save_executable: Option<PathBuf> = None;
+        let mut field_separator: Option<String> = None;
+        let mut non_decimal_data = false;
 
         let mut i = 1;
         while i < args.len() {
@@ -49,6 +58,10 @@ impl AwkArgs {
                     dump = true;
                     i += 1;
                 }
+                "--non-decimal-data" => {
+                    non_decimal_data = true;
+                    i += 1;
+                }
                 "--save" => {
                     if let Some(next) = args.get(i+1) {
                         save_executable = Some(PathBuf::from(next));
@@ -58,8 +71,23 @@ impl AwkArgs {
                     }
                     i += 2;
                 }
+                "-F" => {
+                    if let Some(next) = args.get(i + 1) {
+                        field_separator = Some(next.clone());
+                    } else {
+                        print_help();
+                        eprintln!("-F must be followed by a field separator");
+                        return Err(());
+                    }
+                    i += 2;
+                }
+                // -Fsepstring
+                arg if arg.starts_with("-F") => {
+                    field_separator = Some(arg[2..].to_string());
+                    i += 1;
+                }
                 "-f" => {
-                    if program != None {
+                    if program.is_some() {
                         print_help();
                         eprintln!("Cannot specify multiple programs!");
                         return Err(());
@@ -67,7 +95,7 @@ impl AwkArgs {
                     let next = match args.get(i + 1) {
                         None => {
                             print_help();
-                            eprint!("-f must be followed by a file name\n");
+                            eprintln!("-f must be followed by a file name");
                             return Err(());
                         }
                         Some(path) => path,
@@ -76,7 +104,7 @@ impl AwkArgs {
                     i += 2;
                 }
                 _ => {
-                    if program == None {
+                    if program.is_none() {
                         program = Some(ProgramType::CLI(args[i].clone()));
                     } else {
                         files.push(args[i].clone());
@@ -93,6 +121,6 @@ impl AwkArgs {
             }
             Some(prog) => prog
         };
-        Ok(AwkArgs { dump, program, files, save_executable })
+        Ok(AwkArgs { dump, program, files, save_executable, field_separator, non_decimal_data })
     }
 }
\ No newline at end of file
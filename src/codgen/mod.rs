@@ -3,32 +3,37 @@ mod types;
 mod subroutines;
 mod variable_extract;
 
-use std::collections::{HashSet};
+use std::collections::HashMap;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::execution_engine::{ExecutionEngine};
 use inkwell::module::{Linkage, Module};
-use inkwell::{FloatPredicate, IntPredicate, OptimizationLevel};
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, OptimizationLevel};
 use inkwell::basic_block::BasicBlock;
 use inkwell::memory_buffer::MemoryBuffer;
-use inkwell::types::{FloatType, IntType};
-use inkwell::values::{AggregateValue, AnyValue, BasicMetadataValueEnum, BasicValue, BasicValueEnum, FloatValue, FunctionValue, InstructionOpcode, IntValue, PointerValue};
+use inkwell::types::{BasicMetadataTypeEnum, BasicType, FloatType, IntType};
+use inkwell::values::{AnyValue, BasicMetadataValueEnum, BasicValue, CallSiteValue, FloatValue, FunctionValue, IntValue, PointerValue};
 use crate::{BinOp, Expr};
-use crate::codgen::scopes::{ScopeInfo, Scopes};
+use crate::lexer::LogicalOp;
+use crate::codgen::scopes::{Scopes, SPECIAL_ARRAYS, SPECIAL_SCALARS};
 use crate::codgen::subroutines::Subroutines;
 use crate::codgen::types::{pad, Types};
-use crate::parser::{Stmt};
+use crate::codgen::variable_extract::Vars;
+use crate::parser::{Builtin, Function, GetlineSource, LValue, Redirect, Stmt};
+use crate::transformer::LoweredProgram;
 
-/// Value type
-///
-/// tag: u8   (0 is f64, 2 is string)
-/// | number f64
-/// | string
+// Value type
+//
+// tag: u8   (0 is f64, 2 is string)
+// | number f64
+// | string
 
-pub fn compile(prog: Stmt, files: &[String], dump: bool) -> MemoryBuffer {
+// field_separator is the value of -F, which sets FS before BEGIN runs. non_decimal_data lets
+// strings with hex, inf and nan convert to numbers.
+pub fn compile(prog: LoweredProgram, files: &[String], field_separator: Option<&str>, non_decimal_data: bool, dump: bool) -> MemoryBuffer {
     let context = Context::create();
     let mut codegen = CodeGen::new(&context);
-    codegen.compile(prog, &context, files, dump)
+    codegen.compile(prog, &context, files, field_separator, non_decimal_data, dump)
 }
 
 pub enum Value {
@@ -36,19 +41,57 @@ pub enum Value {
     ConstString(String),
 }
 
-const ROOT: &'static str = "main";
+const ROOT: &str = "main";
 const FLOAT_TAG: u8 = 0;
 const STRING_TAG: u8 = 1;
 // Should be freed upon overwrite
 const CONST_STRING_TAG: u8 = 2; // Should not
+// A string read from input that looks numeric. Owned like STRING_TAG.
+const STRNUM_TAG: u8 = 3;
+// A variable or element that was never assigned. It is "" as a string and 0 as a number.
+const UNINIT_TAG: u8 = 4;
 
 struct CodeGen<'ctx> {
     module: Module<'ctx>,
     builder: Builder<'ctx>,
+    // Owns the module, it is never called since the program is compiled to bitcode instead
+    #[allow(dead_code)]
     execution_engine: ExecutionEngine<'ctx>,
     scopes: Scopes<'ctx>,
     types: Types<'ctx>,
     subroutines: Subroutines<'ctx>,
+    // Each user function and whether each of its parameters is an array
+    functions: HashMap<String, (FunctionValue<'ctx>, Vec<bool>)>,
+    // Set while compiling the body of a user function
+    function_ctx: Option<FunctionCtx<'ctx>>,
+    // Enclosing loops, innermost last
+    loops: Vec<LoopTargets<'ctx>>,
+    // Set while compiling the record loop. The block `next` jumps to and how many loops enclose
+    // the record loop.
+    next_target: Option<(BasicBlock<'ctx>, usize)>,
+    // Set when `next` runs inside a function. The function returns and each caller carries on the
+    // jump until it reaches the record loop.
+    next_pending: PointerValue<'ctx>,
+    // Runs the END blocks. Called at the end of main and by `exit`. Only runs them the first time.
+    end_function: FunctionValue<'ctx>,
+    end_started: PointerValue<'ctx>,
+    // i64 status main returns. Set by `exit expr`
+    exit_code: PointerValue<'ctx>,
+}
+
+// Where break and continue jump to inside a loop
+struct LoopTargets<'ctx> {
+    continue_bb: BasicBlock<'ctx>,
+    break_bb: BasicBlock<'ctx>,
+    // The iterator of a for (k in a) loop which must be freed when `next` leaves the loop
+    iter: Option<PointerValue<'ctx>>,
+}
+
+struct FunctionCtx<'ctx> {
+    // Scalar locals (params) which must be freed when the function returns
+    locals: Vec<ValueT<'ctx>>,
+    // Out params the return value is written to
+    ret: ValueT<'ctx>,
 }
 
 type ValueT<'ctx> = (PointerValue<'ctx>, PointerValue<'ctx>);
@@ -60,6 +103,13 @@ impl<'ctx> CodeGen<'ctx> {
         let types = Types::new(context, &module);
         let mut builder = context.create_builder();
         let subroutines = Subroutines::new(context, &module, &types, &mut builder);
+        let end_function = module.add_function("end-blocks", context.void_type().fn_type(&[], false), None);
+        let end_started = module.add_global(context.bool_type(), None, "end-started");
+        end_started.set_initializer(&context.bool_type().const_int(0, false));
+        let exit_code = module.add_global(context.i64_type(), None, "exit-code");
+        exit_code.set_initializer(&context.i64_type().const_int(0, false));
+        let next_pending = module.add_global(context.bool_type(), None, "next-pending");
+        next_pending.set_initializer(&context.bool_type().const_int(0, false));
 
         let codegen = CodeGen {
             module,
@@ -68,11 +118,24 @@ impl<'ctx> CodeGen<'ctx> {
             types,
             scopes: Scopes::new(),
             subroutines,
+            functions: HashMap::new(),
+            function_ctx: None,
+            loops: vec![],
+            next_target: None,
+            next_pending: next_pending.as_pointer_value(),
+            end_function,
+            end_started: end_started.as_pointer_value(),
+            exit_code: exit_code.as_pointer_value(),
         };
         codegen
     }
 
-    fn compile(&mut self, prog: Stmt, context: &'ctx Context, files: &[String], dump: bool) -> MemoryBuffer {
+    fn compile(&mut self, prog: LoweredProgram, context: &'ctx Context, files: &[String], field_separator: Option<&str>, non_decimal_data: bool, dump: bool) -> MemoryBuffer {
+        let vars = variable_extract::extract(&prog.main, &prog.end, &prog.functions);
+        // Every function is declared up front so they can be called before they are defined
+        self.declare_functions(&prog.functions, &vars, context);
+        self.define_globals(&vars, context);
+
         let i64_type = context.i64_type();
         let i64_func = i64_type.fn_type(&[], false);
         let function = self.module.add_function(ROOT, i64_func, Some(Linkage::External));
@@ -90,23 +153,28 @@ impl<'ctx> CodeGen<'ctx> {
             self.builder.build_store(malloced_array, const_str);
             self.builder.build_call(self.types.add_file, &[malloced_array.into()], "add file");
         }
+        if non_decimal_data {
+            self.builder.build_call(self.types.allow_non_decimal_data, &[], "allow_non_decimal_data");
+        }
         self.builder.build_call(self.types.init, &[], "done adding files call init!");
+        if let Some(separator) = field_separator {
+            let separator = self.create_value(Value::ConstString(separator.to_string()), context);
+            self.store_owned(&LValue::Variable(String::from("FS")), separator, context);
+        }
 
-        self.define_all_vars(&prog, context);
-        let final_bb = self.compile_stmt(&prog, context);
+        self.init_arrays(&vars);
+        let final_bb = self.compile_stmt(&prog.main, context);
 
         self.builder.position_at_end(final_bb);
-        // If the last instruction isn't a return, add one and return 0
-        let zero = context.i64_type().const_int(0, false);
-        match self.builder.get_insert_block().unwrap().get_last_instruction() {
-            None => { self.builder.build_return(Some(&zero)); } // No instructions in the block
-            Some(last) => {
-                match last.get_opcode() {
-                    InstructionOpcode::Return => {} // it is a return, do nothing
-                    _ => { self.builder.build_return(Some(&zero)); }
-                }
-            }
-        };
+        self.builder.build_call(self.end_function, &[], "run_end");
+        self.builder.build_call(self.types.close_all_streams, &[], "close_all_streams");
+        let exit_code = self.builder.build_load(self.exit_code, "exit_code").into_int_value();
+        self.builder.build_return(Some(&exit_code));
+
+        self.compile_end(&prog.end, context);
+        for function in prog.functions.iter() {
+            self.compile_function(function, context);
+        }
 
         if dump {
             println!("{}", self.module.print_to_string().to_string().replace("\\n", "\n"));
@@ -121,13 +189,29 @@ impl<'ctx> CodeGen<'ctx> {
     }
 
     fn alloc(&mut self, tag: IntValue<'ctx>, value: FloatValue<'ctx>, context: &'ctx Context) -> (PointerValue<'ctx>, PointerValue<'ctx>) {
-        let tag_ptr = self.builder.build_alloca(context.i8_type(), "tag");
-        let value_ptr = self.builder.build_alloca(context.f64_type(), "value");
+        let tag_ptr = self.build_entry_alloca(context.i8_type(), "tag", context);
+        let value_ptr = self.build_entry_alloca(context.f64_type(), "value", context);
         self.builder.build_store(tag_ptr, tag);
         self.builder.build_store(value_ptr, value);
         (tag_ptr, value_ptr)
     }
 
+    // Allocas go at the top of the entry block so loops don't grow the stack each iteration
+    fn build_entry_alloca<T: BasicType<'ctx>>(&self, ty: T, name: &str, context: &'ctx Context) -> PointerValue<'ctx> {
+        let builder = context.create_builder();
+        let entry = self.function().get_first_basic_block().expect("function to have an entry block");
+        match entry.get_first_instruction() {
+            Some(first) => builder.position_before(&first),
+            None => builder.position_at_end(entry),
+        }
+        builder.build_alloca(ty, name)
+    }
+
+    // The function currently being compiled into
+    fn function(&self) -> FunctionValue<'ctx> {
+        self.builder.get_insert_block().and_then(|bb| bb.get_parent()).expect("builder to be inside a function")
+    }
+
 
     fn create_value(&mut self, value: Value, context: &'ctx Context) -> ValueT<'ctx> {
         let zero_i8 = context.i8_type().const_int(FLOAT_TAG as u64, false);
@@ -138,26 +222,302 @@ impl<'ctx> CodeGen<'ctx> {
                 self.alloc(zero_i8, context.f64_type().const_float(num), context)
             }
             Value::ConstString(value) => {
-                let name = format!("const-str-{}", value);
-                let global_value = self.builder.build_global_string_ptr(&value, &name);
-                let global_ptr = global_value.as_pointer_value();
+                let global_ptr = if value.contains('\0') {
+                    self.nul_string_ptr(&value, context)
+                } else {
+                    let name = format!("const-str-{}", value);
+                    self.builder.build_global_string_ptr(&value, &name).as_pointer_value()
+                };
                 let float_ptr = self.cast_ptr_to_float(global_ptr, context);
-                let tag_ptr = self.builder.build_alloca(context.i8_type(), "const-str-tag");
-                let value_ptr = self.builder.build_alloca(context.f64_type(), "const-str-value");
-                self.builder.build_store(tag_ptr, two_i8);
-                self.builder.build_store(value_ptr, float_ptr);
-                (tag_ptr, value_ptr)
+                self.alloc(two_i8, float_ptr, context)
             }
         }
     }
 
-    fn define_all_vars(&mut self, prog: &Stmt, context: &'ctx Context) {
-        let vars = variable_extract::extract(prog);
-        let zero_i8 = context.i8_type().const_int(0, false);
+    // A string literal holding a NUL, eg. "\0". The bytes are stored as they are and the runtime is
+    // told the length, since as a C string it would end at the NUL.
+    fn nul_string_ptr(&mut self, value: &str, context: &'ctx Context) -> PointerValue<'ctx> {
+        let bytes = context.const_string(value.as_bytes(), true);
+        let global = self.module.add_global(bytes.get_type(), None, "const-str-nul");
+        global.set_initializer(&bytes);
+        let ptr = self.builder.build_pointer_cast(global.as_pointer_value(), context.i8_type().ptr_type(AddressSpace::Generic), "nul_string");
+        let length = context.i64_type().const_int(value.len() as u64, false);
+        self.builder.build_call(self.types.string_with_nul, &[ptr.into(), length.into()], "string_with_nul");
+        ptr
+    }
+
+    // Globals are module level so user functions can reach them. The names contain a '-' so they
+    // can't collide with symbols from the runtime.
+    fn define_globals(&mut self, vars: &Vars, context: &'ctx Context) {
+        let zero_i8 = context.i8_type().const_int(UNINIT_TAG as u64, false);
         let zero = context.f64_type().const_float(0.0);
-        for var in vars {
-            let ptrs = self.alloc(zero_i8, zero, context);
-            self.scopes.insert(var, ptrs);
+        for var in vars.scalars.iter() {
+            let tag = self.module.add_global(context.i8_type(), None, &format!("{}-tag", var));
+            tag.set_initializer(&zero_i8);
+            let value = self.module.add_global(context.f64_type(), None, &format!("{}-value", var));
+            value.set_initializer(&zero);
+            self.scopes.insert(var.clone(), (tag.as_pointer_value(), value.as_pointer_value()));
+        }
+        let array_type = context.i8_type().ptr_type(AddressSpace::Generic);
+        for array in vars.arrays.iter() {
+            let slot = self.module.add_global(array_type, None, &format!("{}-array", array));
+            slot.set_initializer(&array_type.const_null());
+            self.scopes.insert_array(array.clone(), slot.as_pointer_value());
+        }
+        // Special variables are defined by the runtime, these only declare them
+        for name in SPECIAL_SCALARS.iter() {
+            let tag = self.module.add_global(context.i8_type(), None, &format!("special_{}_tag", name));
+            let value = self.module.add_global(context.f64_type(), None, &format!("special_{}_value", name));
+            self.scopes.insert_special(name.to_string(), (tag.as_pointer_value(), value.as_pointer_value()));
+        }
+        for name in SPECIAL_ARRAYS.iter() {
+            let slot = self.module.add_global(array_type, None, &format!("special_{}", name));
+            self.scopes.insert_special_array(name.to_string(), slot.as_pointer_value());
+        }
+    }
+
+    fn init_arrays(&mut self, vars: &Vars) {
+        for array in vars.arrays.iter() {
+            let new_array = self.builder.build_call(self.types.new_array, &[], "new_array").as_any_value_enum().into_pointer_value();
+            let slot = self.scopes.lookup_array(array).expect("array to be defined");
+            self.builder.build_store(slot, new_array);
+        }
+    }
+
+    // Scalars are passed as (tag, value) and arrays as a pointer to the runtime's array. The last
+    // two params are out params for the return value.
+    fn declare_functions(&mut self, functions: &[Function], vars: &Vars, context: &'ctx Context) {
+        for function in functions {
+            if self.functions.contains_key(&function.name) {
+                panic!("Function {} is defined more than once", function.name);
+            }
+            let is_array = vars.function_arrays.get(&function.name).expect("function to be analyzed").clone();
+            let mut params: Vec<BasicMetadataTypeEnum> = vec![];
+            for array in is_array.iter() {
+                if *array {
+                    params.push(context.i8_type().ptr_type(AddressSpace::Generic).into());
+                } else {
+                    params.push(context.i8_type().into());
+                    params.push(context.f64_type().into());
+                }
+            }
+            params.push(context.i8_type().ptr_type(AddressSpace::Generic).into());
+            params.push(context.f64_type().ptr_type(AddressSpace::Generic).into());
+            let fn_type = context.void_type().fn_type(&params, false);
+            let llvm_function = self.module.add_function(&format!("function-{}", function.name), fn_type, None);
+            self.functions.insert(function.name.clone(), (llvm_function, is_array));
+        }
+    }
+
+    fn compile_function(&mut self, function: &Function, context: &'ctx Context) {
+        let (llvm_function, is_array) = self.functions.get(&function.name).expect("function to be declared").clone();
+        let entry = context.append_basic_block(llvm_function, "entry");
+        self.builder.position_at_end(entry);
+        self.scopes.begin_scope();
+
+        let mut locals = vec![];
+        let mut param_idx = 0;
+        for (name, array) in function.args.iter().zip(is_array.iter()) {
+            if *array {
+                let array = llvm_function.get_nth_param(param_idx).unwrap().into_pointer_value();
+                let slot = self.build_entry_alloca(context.i8_type().ptr_type(AddressSpace::Generic), "array-param", context);
+                self.builder.build_store(slot, array);
+                self.scopes.insert_array(name.clone(), slot);
+                param_idx += 1;
+            } else {
+                let tag = llvm_function.get_nth_param(param_idx).unwrap().into_int_value();
+                let value = llvm_function.get_nth_param(param_idx + 1).unwrap().into_float_value();
+                // Scalars are passed by value so the function owns a copy of any string
+                let value = self.builder.build_call(self.types.copy_if_string, &[tag.into(), value.into()], "copy_param").as_any_value_enum().into_float_value();
+                let local = self.alloc(tag, value, context);
+                self.scopes.insert(name.clone(), local);
+                locals.push(local);
+                param_idx += 2;
+            }
+        }
+        let ret_tag = llvm_function.get_nth_param(param_idx).unwrap().into_pointer_value();
+        let ret_value = llvm_function.get_nth_param(param_idx + 1).unwrap().into_pointer_value();
+        self.function_ctx = Some(FunctionCtx { locals, ret: (ret_tag, ret_value) });
+
+        self.compile_stmt(&function.body, context);
+        // Falling off the end of a function returns an uninitialized value
+        self.build_function_return(None, context);
+
+        self.function_ctx = None;
+        self.scopes.end_scope();
+    }
+
+    fn compile_end(&mut self, end: &Stmt, context: &'ctx Context) {
+        let entry = context.append_basic_block(self.end_function, "entry");
+        let run_bb = context.append_basic_block(self.end_function, "run_end");
+        let done_bb = context.append_basic_block(self.end_function, "end_done");
+
+        // `exit` inside an END block calls this again, which must not restart the END blocks
+        self.builder.position_at_end(entry);
+        let started = self.builder.build_load(self.end_started, "end_started").into_int_value();
+        self.builder.build_conditional_branch(started, done_bb, run_bb);
+
+        self.builder.position_at_end(run_bb);
+        self.builder.build_store(self.end_started, context.bool_type().const_int(1, false));
+        self.compile_stmt(end, context);
+        self.builder.build_unconditional_branch(done_bb);
+
+        self.builder.position_at_end(done_bb);
+        self.builder.build_return(None);
+    }
+
+    // Branches to target. Code after a jump is unreachable but still needs a block to be compiled into.
+    fn build_jump(&mut self, target: BasicBlock<'ctx>, context: &'ctx Context) -> BasicBlock<'ctx> {
+        self.builder.build_unconditional_branch(target);
+        let after_jump = context.append_basic_block(self.function(), "after_jump");
+        self.builder.position_at_end(after_jump);
+        after_jump
+    }
+
+    fn build_next(&mut self, context: &'ctx Context) -> BasicBlock<'ctx> {
+        if self.function_ctx.is_some() {
+            self.builder.build_store(self.next_pending, context.bool_type().const_int(1, false));
+            self.build_leave_for_next(context);
+            let after_return = context.append_basic_block(self.function(), "after_return");
+            self.builder.position_at_end(after_return);
+            return after_return;
+        }
+        let (target, depth) = self.next_target.expect("next used outside of a pattern action");
+        self.free_loop_iters(depth);
+        self.build_jump(target, context)
+    }
+
+    // Frees the iterators of the for (k in a) loops from depth inwards, which `next` jumps out of
+    fn free_loop_iters(&mut self, depth: usize) {
+        let iters: Vec<PointerValue> = self.loops[depth..].iter().filter_map(|l| l.iter).collect();
+        for iter in iters {
+            self.builder.build_call(self.types.array_iter_free, &[iter.into()], "array_iter_free");
+        }
+    }
+
+    // Leaves the current function, or jumps to the record loop, for a `next` in this or a called function
+    fn build_leave_for_next(&mut self, context: &'ctx Context) {
+        if self.function_ctx.is_some() {
+            self.free_loop_iters(0);
+            self.build_function_return(None, context);
+        } else if let Some((target, depth)) = self.next_target {
+            self.builder.build_store(self.next_pending, context.bool_type().const_int(0, false));
+            self.free_loop_iters(depth);
+            self.builder.build_unconditional_branch(target);
+        } else {
+            self.builder.build_call(self.types.next_outside_records, &[], "next_outside_records");
+            self.builder.build_unreachable();
+        }
+    }
+
+    fn build_function_return(&mut self, result: Option<&Expr>, context: &'ctx Context) {
+        let (tag, value) = match result {
+            Some(expr) => {
+                let result = self.compile_expr(expr, context);
+                let (tag, mut value) = self.load(result);
+                // Variables are freed or outlive the call so the caller gets its own copy
+                if let Expr::Variable(_) = expr {
+                    value = self.builder.build_call(self.types.copy_if_string, &[tag.into(), value.into()], "copy_if_string").as_any_value_enum().into_float_value();
+                }
+                (tag, value)
+            }
+            None => (context.i8_type().const_int(UNINIT_TAG as u64, false), context.f64_type().const_float(0.0)),
+        };
+        let ctx = self.function_ctx.as_ref().expect("to be inside a function");
+        let (ret, locals) = (ctx.ret, ctx.locals.clone());
+        self.builder.build_store(ret.0, tag);
+        self.builder.build_store(ret.1, value);
+        for local in locals {
+            let args = self.value_for_ffi(local);
+            self.builder.build_call(self.subroutines.free_if_string, &args, "free-local");
+        }
+        self.builder.build_return(None);
+    }
+
+    fn compile_call(&mut self, name: &str, args: &[Expr], context: &'ctx Context) -> ValueT<'ctx> {
+        let (callee, is_array) = match self.functions.get(name) {
+            Some(function) => function.clone(),
+            None => panic!("Function {} is not defined", name),
+        };
+        if args.len() > is_array.len() {
+            panic!("Function {} takes {} arguments but was called with {}", name, is_array.len(), args.len());
+        }
+        let mut call_args: Vec<BasicMetadataValueEnum> = vec![];
+        let mut temps = vec![];
+        let mut fresh_arrays = vec![];
+        for (idx, array) in is_array.iter().enumerate() {
+            match (args.get(idx), *array) {
+                (Some(Expr::Variable(var)), true) => call_args.push(self.lookup_array(var).into()),
+                (Some(arg), true) => panic!("Cannot pass {} to array parameter of {}", arg, name),
+                (None, true) => {
+                    // Missing args are locals. An array local starts out empty.
+                    let array = self.builder.build_call(self.types.new_array, &[], "new_array").as_any_value_enum().into_pointer_value();
+                    fresh_arrays.push(array);
+                    call_args.push(array.into());
+                }
+                (Some(arg), false) => {
+                    let value = self.compile_expr(arg, context);
+                    call_args.extend(self.value_for_ffi(value));
+                    temps.push((arg, value));
+                }
+                (None, false) => {
+                    call_args.push(context.i8_type().const_int(UNINIT_TAG as u64, false).into());
+                    call_args.push(context.f64_type().const_float(0.0).into());
+                }
+            }
+        }
+        let result = self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), context.f64_type().const_float(0.0), context);
+        call_args.push(result.0.into());
+        call_args.push(result.1.into());
+        self.builder.build_call(callee, &call_args, "call");
+        for (arg, value) in temps {
+            self.free_if_temp(arg, value);
+        }
+        for array in fresh_arrays {
+            self.builder.build_call(self.types.free_array, &[array.into()], "free_array");
+        }
+        // The function ran `next`
+        let pending = self.builder.build_load(self.next_pending, "next_pending").into_int_value();
+        let next_bb = context.append_basic_block(self.function(), "call_next");
+        let after_bb = context.append_basic_block(self.function(), "after_call");
+        self.builder.build_conditional_branch(pending, next_bb, after_bb);
+        self.builder.position_at_end(next_bb);
+        self.build_leave_for_next(context);
+        self.builder.position_at_end(after_bb);
+        result
+    }
+
+    fn lookup_array(&mut self, name: &str) -> PointerValue<'ctx> {
+        let slot = self.scopes.lookup_array(name).expect("array to be defined");
+        self.builder.build_load(slot, "array").into_pointer_value()
+    }
+
+    // Builds the key for array[a, b, c] by joining the subscripts with SUBSEP.
+    fn compile_subscript(&mut self, indices: &[Expr], context: &'ctx Context) -> ValueT<'ctx> {
+        let mut key = self.compile_expr(&indices[0], context);
+        for (i, index) in indices.iter().enumerate().skip(1) {
+            let next = self.compile_expr(index, context);
+            let mut args = self.value_for_ffi(key);
+            args.extend(self.value_for_ffi(next));
+            let joined = self.builder.build_call(self.types.subsep_join, &args, "subsep_join").as_any_value_enum().into_float_value();
+            if i == 1 {
+                self.free_if_temp(&indices[0], key);
+            } else {
+                let args = self.value_for_ffi(key);
+                self.builder.build_call(self.subroutines.free_if_string, &args, "free-key");
+            }
+            self.free_if_temp(index, next);
+            key = self.alloc(context.i8_type().const_int(STRING_TAG as u64, false), joined, context);
+        }
+        key
+    }
+
+    fn free_subscript(&mut self, indices: &[Expr], key: ValueT<'ctx>) {
+        if indices.len() == 1 {
+            self.free_if_temp(&indices[0], key);
+        } else {
+            let args = self.value_for_ffi(key);
+            self.builder.build_call(self.subroutines.free_if_string, &args, "free-key");
         }
     }
 
@@ -170,17 +530,43 @@ impl<'ctx> CodeGen<'ctx> {
 
     fn compile_to_bool(&mut self, expr: &Expr, context: &'ctx Context) -> IntValue<'ctx> {
         let result = self.compile_expr(expr, context);
-        let (tag, value) = self.load(result);
-        let tag_is_zero = self.builder.build_int_compare(IntPredicate::EQ, tag, context.i8_type().const_int(0, false), "tag_is_zero");
-        let value_is_zero_f64 = self.builder.build_float_compare(FloatPredicate::OEQ, value, context.f64_type().const_float(0.0), "value_is_zero_f64");
-        let zero_f64 = self.builder.build_and(value_is_zero_f64, tag_is_zero, "zero_f64");
-        let result = self.builder.build_not(zero_f64, "predicate");
-        result
+        let truthy = self.build_truthy(result, context);
+        self.free_if_temp(expr, result);
+        truthy
+    }
+
+    // Numbers are true when non-zero. Strings are true when non-empty, except strnums which are
+    // true when their number is non-zero.
+    fn build_truthy(&mut self, value: ValueT<'ctx>, context: &'ctx Context) -> IntValue<'ctx> {
+        let function = self.function();
+        let string_bb = context.append_basic_block(function, "truthy_string");
+        let done_bb = context.append_basic_block(function, "truthy_done");
+
+        let (tag, value) = self.load(value);
+        let number_bb = self.builder.get_insert_block().unwrap();
+        let is_number = self.builder.build_int_compare(IntPredicate::EQ, tag, context.i8_type().const_int(FLOAT_TAG as u64, false), "is_number");
+        let number_truthy = self.builder.build_float_compare(FloatPredicate::ONE, value, context.f64_type().const_float(0.0), "number_truthy");
+        self.builder.build_conditional_branch(is_number, done_bb, string_bb);
+
+        self.builder.position_at_end(string_bb);
+        let string_truthy = self.builder.build_call(self.types.string_truthy, &[tag.into(), value.into()], "string_truthy").as_any_value_enum().into_float_value();
+        let string_truthy = self.builder.build_float_compare(FloatPredicate::ONE, string_truthy, context.f64_type().const_float(0.0), "string_truthy_bool");
+        self.builder.build_unconditional_branch(done_bb);
+
+        self.builder.position_at_end(done_bb);
+        let phi = self.builder.build_phi(context.bool_type(), "truthy");
+        phi.add_incoming(&[(&number_truthy, number_bb), (&string_truthy, string_bb)]);
+        phi.as_basic_value().into_int_value()
+    }
+
+    fn bool_to_value(&mut self, value: IntValue<'ctx>, context: &'ctx Context) -> ValueT<'ctx> {
+        let float = self.builder.build_unsigned_int_to_float(value, context.f64_type(), "bool_to_float");
+        self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), float, context)
     }
 
     fn value_for_ffi(&mut self, result: ValueT<'ctx>) -> Vec<BasicMetadataValueEnum<'ctx>> {
         let (tag, value) = self.load(result);
-        return vec![tag.into(), value.into()];
+        vec![tag.into(), value.into()]
     }
 
     fn compile_stmt(&mut self, stmt: &Stmt, context: &'ctx Context) -> BasicBlock<'ctx> {
@@ -190,12 +576,11 @@ impl<'ctx> CodeGen<'ctx> {
                 // while_test -> while_body, while_continue
                 // while_body -> while_test
                 // while_continue -> END
-                let root = self.module.get_function(ROOT).expect("root to exist");
+                let function = self.function();
 
-                let pred = self.builder.get_insert_block().unwrap();
-                let while_test_bb = context.append_basic_block(root, "while_test");
-                let while_body_bb = context.append_basic_block(root, "while_body");
-                let continue_bb = context.append_basic_block(root, "while_continue");
+                let while_test_bb = context.append_basic_block(function, "while_test");
+                let while_body_bb = context.append_basic_block(function, "while_body");
+                let continue_bb = context.append_basic_block(function, "while_continue");
 
                 self.builder.build_unconditional_branch(while_test_bb);
                 self.builder.position_at_end(while_test_bb);
@@ -203,38 +588,178 @@ impl<'ctx> CodeGen<'ctx> {
                 self.builder.build_conditional_branch(test_result_bool, while_body_bb, continue_bb);
 
                 self.builder.position_at_end(while_body_bb);
-                self.compile_stmt(body, context);
+                if let Expr::NextLine = test {
+                    // The record loop built by the transformer. It isn't a loop as far as break and continue are concerned.
+                    self.next_target = Some((while_test_bb, self.loops.len()));
+                    self.compile_stmt(body, context);
+                    self.next_target = None;
+                } else {
+                    self.compile_loop_body(body, while_test_bb, continue_bb, None, context);
+                }
                 self.builder.build_unconditional_branch(while_test_bb);
 
                 self.builder.position_at_end(continue_bb);
                 return continue_bb;
             }
-            Stmt::Expr(expr) => {
-                self.compile_expr(expr, context);
+            Stmt::DoWhile(body, test) => {
+                // INIT -> do_body
+                // do_body -> do_test
+                // do_test -> do_body, do_continue
+                // do_continue -> END
+                let function = self.function();
+
+                let body_bb = context.append_basic_block(function, "do_body");
+                let test_bb = context.append_basic_block(function, "do_test");
+                let continue_bb = context.append_basic_block(function, "do_continue");
+
+                self.builder.build_unconditional_branch(body_bb);
+                self.builder.position_at_end(body_bb);
+                self.compile_loop_body(body, test_bb, continue_bb, None, context);
+                self.builder.build_unconditional_branch(test_bb);
+
+                self.builder.position_at_end(test_bb);
+                let test_result_bool = self.compile_to_bool(test, context);
+                self.builder.build_conditional_branch(test_result_bool, body_bb, continue_bb);
+
+                self.builder.position_at_end(continue_bb);
+                return continue_bb;
             }
-            Stmt::Print(expr) => {
+            Stmt::For(init, test, incr, body) => {
+                // INIT -> for_test
+                // for_test -> for_body, for_continue
+                // for_body -> for_incr
+                // for_incr -> for_test
+                // for_continue -> END
+                let function = self.function();
+
+                let test_bb = context.append_basic_block(function, "for_test");
+                let body_bb = context.append_basic_block(function, "for_body");
+                let incr_bb = context.append_basic_block(function, "for_incr");
+                let continue_bb = context.append_basic_block(function, "for_continue");
+
+                if let Some(init) = init {
+                    self.compile_unused(init, context);
+                }
+                self.builder.build_unconditional_branch(test_bb);
+                self.builder.position_at_end(test_bb);
+                match test {
+                    Some(test) => {
+                        let test_result_bool = self.compile_to_bool(test, context);
+                        self.builder.build_conditional_branch(test_result_bool, body_bb, continue_bb);
+                    }
+                    None => { self.builder.build_unconditional_branch(body_bb); }
+                }
+
+                self.builder.position_at_end(body_bb);
+                self.compile_loop_body(body, incr_bb, continue_bb, None, context);
+                self.builder.build_unconditional_branch(incr_bb);
+
+                self.builder.position_at_end(incr_bb);
+                if let Some(incr) = incr {
+                    self.compile_unused(incr, context);
+                }
+                self.builder.build_unconditional_branch(test_bb);
+
+                self.builder.position_at_end(continue_bb);
+                return continue_bb;
+            }
+            Stmt::Expr(expr) => self.compile_unused(expr, context),
+            Stmt::Print(expr, redirect) => {
                 let result = self.compile_expr(expr, context);
-                let result = self.value_for_ffi(result);
-                self.builder.build_call(self.types.print, &result, "print_value_call");
+                let stream = self.compile_redirect(redirect.as_ref(), context);
+                let mut args: Vec<BasicMetadataValueEnum> = vec![stream.into()];
+                args.extend(self.value_for_ffi(result));
+                self.builder.build_call(self.types.print, &args, "print_value_call");
+                self.free_if_temp(expr, result);
             }
-            Stmt::Assign(name, expr) => {
-                let fin = self.compile_expr(expr, context);
-                if let Some(existing) = self.scopes.lookup(name) {
-                    let args = self.value_for_ffi(existing);
-                    self.builder.build_call(self.subroutines.free_if_string, &args, "call-free-if-str");
-                    let fin = self.load(fin);
-                    self.builder.build_store(existing.0, fin.0);
-                    self.builder.build_store(existing.1, fin.1);
+            Stmt::Printf(format, args, redirect) => {
+                let stream = self.compile_redirect(redirect.as_ref(), context);
+                self.build_format_call(self.types.printf, Some(stream), format, args, context);
+            }
+            Stmt::Delete(name, indices) => {
+                let array = self.lookup_array(name);
+                if let Some(indices) = indices {
+                    let key = self.compile_subscript(indices, context);
+                    let mut args: Vec<BasicMetadataValueEnum> = vec![array.into()];
+                    args.extend(self.value_for_ffi(key));
+                    self.builder.build_call(self.types.array_delete, &args, "array_delete");
+                    self.free_subscript(indices, key);
                 } else {
-                    panic!("Undefined variable {}", name);
+                    self.builder.build_call(self.types.array_clear, &[array.into()], "array_clear");
                 }
             }
-            Stmt::Return(result) => {
-                let fin = match result {
-                    None => context.i64_type().const_int(0, false),
-                    Some(val) => self.compile_to_bool(val, context),
-                };
-                self.builder.build_return(Some(&fin));
+            Stmt::ForIn(var, name, body) => {
+                // INIT -> for_in_test
+                // for_in_test -> for_in_body, for_in_continue
+                // for_in_body -> for_in_test
+                // for_in_continue -> END
+                // Keys are snapshotted up front so the body may add or delete elements.
+                let function = self.function();
+                let array = self.lookup_array(name);
+                let iter = self.builder.build_call(self.types.array_iter, &[array.into()], "array_iter").as_any_value_enum().into_pointer_value();
+
+                let test_bb = context.append_basic_block(function, "for_in_test");
+                let body_bb = context.append_basic_block(function, "for_in_body");
+                let continue_bb = context.append_basic_block(function, "for_in_continue");
+
+                self.builder.build_unconditional_branch(test_bb);
+                self.builder.position_at_end(test_bb);
+                let has_next = self.builder.build_call(self.types.array_iter_has_next, &[iter.into()], "has_next").as_any_value_enum().into_float_value();
+                let has_next = self.builder.build_float_compare(FloatPredicate::ONE, has_next, context.f64_type().const_float(0.0), "has_next_bool");
+                self.builder.build_conditional_branch(has_next, body_bb, continue_bb);
+
+                self.builder.position_at_end(body_bb);
+                let key = self.builder.build_call(self.types.array_iter_next, &[iter.into()], "next_key").as_any_value_enum().into_float_value();
+                let existing = self.scopes.lookup(var).expect("for in variable to be defined");
+                let args = self.value_for_ffi(existing);
+                self.builder.build_call(self.subroutines.free_if_string, &args, "call-free-if-str");
+                self.builder.build_store(existing.0, context.i8_type().const_int(STRING_TAG as u64, false));
+                self.builder.build_store(existing.1, key);
+                self.variable_assigned(var);
+                // break jumps to for_in_continue which frees the iterator
+                self.compile_loop_body(body, test_bb, continue_bb, Some(iter), context);
+                self.builder.build_unconditional_branch(test_bb);
+
+                self.builder.position_at_end(continue_bb);
+                self.builder.build_call(self.types.array_iter_free, &[iter.into()], "array_iter_free");
+                return continue_bb;
+            }
+            Stmt::Return(result) if self.function_ctx.is_some() => {
+                self.build_function_return(result.as_ref(), context);
+                // Anything after a return is unreachable but still needs a block to be compiled into
+                let after_return = context.append_basic_block(self.function(), "after_return");
+                self.builder.position_at_end(after_return);
+                return after_return;
+            }
+            Stmt::Return(_) => panic!("return used outside of a function"),
+            Stmt::Break => {
+                let target = self.loops.last().expect("break used outside of a loop").break_bb;
+                return self.build_jump(target, context);
+            }
+            Stmt::Continue => {
+                let target = self.loops.last().expect("continue used outside of a loop").continue_bb;
+                return self.build_jump(target, context);
+            }
+            Stmt::Next => return self.build_next(context),
+            Stmt::NextFile => {
+                self.builder.build_call(self.types.nextfile, &[], "nextfile");
+                return self.build_next(context);
+            }
+            Stmt::Exit(status) => {
+                if let Some(status) = status {
+                    let value = self.compile_expr(status, context);
+                    let (number, _) = self.build_to_number(value, context);
+                    self.free_if_temp(status, value);
+                    let code = self.builder.build_float_to_signed_int(number, context.i64_type(), "exit_code");
+                    self.builder.build_store(self.exit_code, code);
+                }
+                self.builder.build_call(self.end_function, &[], "run_end");
+                let code = self.builder.build_load(self.exit_code, "exit_code").into_int_value();
+                self.builder.build_call(self.types.exit_program, &[code.into()], "exit_program");
+                self.builder.build_unreachable();
+                let after_exit = context.append_basic_block(self.function(), "after_exit");
+                self.builder.position_at_end(after_exit);
+                return after_exit;
             }
             Stmt::Group(body) => {
                 let mut last_bb = None;
@@ -246,34 +771,35 @@ impl<'ctx> CodeGen<'ctx> {
                 }
             }
             Stmt::If(test, true_blk, false_blk) => {
+                let function = self.function();
                 if let Some(false_blk) = false_blk {
-                    let then_bb = context.append_basic_block(self.module.get_function(ROOT).expect("root to exist"), "then");
-                    let else_bb = context.append_basic_block(self.module.get_function(ROOT).expect("root to exist"), "else");
-                    let continue_bb = context.append_basic_block(self.module.get_function(ROOT).expect("root to exist"), "merge");
+                    let then_bb = context.append_basic_block(function, "then");
+                    let else_bb = context.append_basic_block(function, "else");
+                    let continue_bb = context.append_basic_block(function, "merge");
 
                     let predicate = self.compile_to_bool(test, context);
                     self.builder.build_conditional_branch(predicate, then_bb, else_bb);
 
                     self.builder.position_at_end(then_bb);
-                    let then_bb_final = self.compile_stmt(true_blk, context);
+                    self.compile_stmt(true_blk, context);
                     self.builder.build_unconditional_branch(continue_bb);
 
                     self.builder.position_at_end(else_bb);
-                    let else_bb_final = self.compile_stmt(false_blk, context);
+                    self.compile_stmt(false_blk, context);
                     self.builder.build_unconditional_branch(continue_bb);
 
                     self.builder.position_at_end(continue_bb);
 
                     return continue_bb;
                 } else {
-                    let then_bb = context.append_basic_block(self.module.get_function(ROOT).expect("root to exist"), "then");
-                    let continue_bb = context.append_basic_block(self.module.get_function(ROOT).expect("root to exist"), "continue");
+                    let then_bb = context.append_basic_block(function, "then");
+                    let continue_bb = context.append_basic_block(function, "continue");
 
                     let predicate = self.compile_to_bool(test, context);
                     self.builder.build_conditional_branch(predicate, then_bb, continue_bb);
 
                     self.builder.position_at_end(then_bb);
-                    let then_bb_final = self.compile_stmt(true_blk, context);
+                    self.compile_stmt(true_blk, context);
                     self.builder.build_unconditional_branch(continue_bb);
 
                     self.builder.position_at_end(continue_bb);
@@ -284,6 +810,12 @@ impl<'ctx> CodeGen<'ctx> {
         self.builder.get_insert_block().unwrap()
     }
 
+    fn compile_loop_body(&mut self, body: &Stmt, continue_bb: BasicBlock<'ctx>, break_bb: BasicBlock<'ctx>, iter: Option<PointerValue<'ctx>>, context: &'ctx Context) {
+        self.loops.push(LoopTargets { continue_bb, break_bb, iter });
+        self.compile_stmt(body, context);
+        self.loops.pop();
+    }
+
     fn compile_expr(&mut self, expr: &Expr, context: &'ctx Context) -> ValueT<'ctx> {
         match expr {
             Expr::String(str) => {
@@ -293,29 +825,743 @@ impl<'ctx> CodeGen<'ctx> {
             Expr::NumberF64(num) => {
                 self.create_value(Value::Float(*num), context)
             }
-            Expr::BinOp(left, op, right) => {
+            Expr::BinOp(left, op @ (BinOp::Matches | BinOp::NotMatches), right) => {
                 let l = self.compile_expr(left, context);
-                let r = self.compile_expr(right, context);
-
-                let (l, _l_final_bb) = self.build_to_number(l, context);
-                let (r, _r_final_bb) = self.build_to_number(r, context);
+                let r = self.compile_regex_arg(right, context);
+                let mut matched = self.build_match(l, r);
+                self.free_if_temp(left, l);
+                self.free_if_temp(right, r);
+                if *op == BinOp::NotMatches {
+                    matched = self.builder.build_float_sub(context.f64_type().const_float(1.0), matched, "not_matched");
+                }
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), matched, context)
+            }
+            Expr::BinOp(left, op @ (BinOp::Less | BinOp::LessEq | BinOp::EqEq | BinOp::BangEq | BinOp::GreaterEq | BinOp::Greater), right) => {
+                self.compile_compare(left, op, right, context)
+            }
+            Expr::BinOp(left, op, right) => {
+                let l = self.compile_number(left, context);
+                let r = self.compile_number(right, context);
                 self.build_f64_binop(l, r, op, context)
             }
-            Expr::Column(expr) => {
-                let res = self.compile_expr(expr, context);
-                let args = self.value_for_ffi(res);
-                let float_ptr = self.builder.build_call(self.types.column, &args, "get_column");
-                let one = context.i8_type().const_int(1, false);
-                self.alloc(one, float_ptr.as_any_value_enum().into_float_value(), context)
+            Expr::Concat(parts) => {
+                let (args, compiled) = self.build_value_arrays(parts, context);
+                let result = self.builder.build_call(self.types.concat, &args, "concat").as_any_value_enum().into_float_value();
+                for (part, value) in compiled {
+                    self.free_if_temp(part, value);
+                }
+                self.alloc(context.i8_type().const_int(STRING_TAG as u64, false), result, context)
+            }
+            Expr::Regex(regex) => {
+                // A bare regex is shorthand for `$0 ~ /regex/`
+                let line = self.compile_expr(&Expr::Column(Box::new(Expr::NumberF64(0.0))), context);
+                let regex = self.create_value(Value::ConstString(regex.clone()), context);
+                let matched = self.build_match(line, regex);
+                let line_args = self.value_for_ffi(line);
+                self.builder.build_call(self.subroutines.free_if_string, &line_args, "free-line");
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), matched, context)
+            }
+            Expr::Column(col) => {
+                let index = self.compile_expr(col, context);
+                let result = self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), context.f64_type().const_float(0.0), context);
+                let mut args = self.value_for_ffi(index);
+                args.push(result.0.into());
+                args.push(result.1.into());
+                self.builder.build_call(self.types.column, &args, "get_column");
+                self.free_if_temp(col, index);
+                result
+            }
+            Expr::ArrayIndex(name, indices) => {
+                // Reading an element creates it. The runtime hands back a copy we own.
+                let array = self.lookup_array(name);
+                let key = self.compile_subscript(indices, context);
+                let result = self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), context.f64_type().const_float(0.0), context);
+                let mut args: Vec<BasicMetadataValueEnum> = vec![array.into()];
+                args.extend(self.value_for_ffi(key));
+                args.push(result.0.into());
+                args.push(result.1.into());
+                self.builder.build_call(self.types.array_get, &args, "array_get");
+                self.free_subscript(indices, key);
+                result
             }
-            Expr::LogicalOp(_, _, _) => { panic!("logic not done yet") }
-            Expr::Call => {
+            Expr::InArray(indices, name) => {
+                let array = self.lookup_array(name);
+                let key = self.compile_subscript(indices, context);
+                let mut args: Vec<BasicMetadataValueEnum> = vec![array.into()];
+                args.extend(self.value_for_ffi(key));
+                let found = self.builder.build_call(self.types.array_in, &args, "array_in").as_any_value_enum().into_float_value();
+                self.free_subscript(indices, key);
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), found, context)
+            }
+            Expr::LogicalOp(left, op, right) => {
+                // a && b only evaluates b when a is true, a || b only when a is false
+                let function = self.function();
+                let right_bb = context.append_basic_block(function, "logic_right");
+                let done_bb = context.append_basic_block(function, "logic_done");
+
+                let left = self.compile_to_bool(left, context);
+                let left_bb = self.builder.get_insert_block().unwrap();
+                match op {
+                    LogicalOp::And => self.builder.build_conditional_branch(left, right_bb, done_bb),
+                    LogicalOp::Or => self.builder.build_conditional_branch(left, done_bb, right_bb),
+                };
+
+                self.builder.position_at_end(right_bb);
+                let right = self.compile_to_bool(right, context);
+                let right_bb = self.builder.get_insert_block().unwrap();
+                self.builder.build_unconditional_branch(done_bb);
+
+                self.builder.position_at_end(done_bb);
+                let phi = self.builder.build_phi(context.bool_type(), "logic");
+                phi.add_incoming(&[(&left, left_bb), (&right, right_bb)]);
+                self.bool_to_value(phi.as_basic_value().into_int_value(), context)
+            }
+            Expr::Assign(lvalue, expr) => self.compile_assign(lvalue, expr, true, context).unwrap(),
+            Expr::AssignOp(lvalue, op, expr) => {
+                let operand = self.compile_expr(expr, context);
+                let (operand_num, _) = self.build_to_number(operand, context);
+                self.free_if_temp(expr, operand);
+                let (_old, new) = self.build_update(lvalue, op, operand_num, context);
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), new, context)
+            }
+            Expr::Ternary(test, if_true, if_false) => {
+                // Both branches produce an owned value which is merged with a phi
+                let function = self.function();
+                let true_bb = context.append_basic_block(function, "ternary_true");
+                let false_bb = context.append_basic_block(function, "ternary_false");
+                let done_bb = context.append_basic_block(function, "ternary_done");
+
+                let test = self.compile_to_bool(test, context);
+                self.builder.build_conditional_branch(test, true_bb, false_bb);
+
+                self.builder.position_at_end(true_bb);
+                let (true_tag, true_value) = self.compile_owned(if_true, context);
+                let true_bb = self.builder.get_insert_block().unwrap();
+                self.builder.build_unconditional_branch(done_bb);
+
+                self.builder.position_at_end(false_bb);
+                let (false_tag, false_value) = self.compile_owned(if_false, context);
+                let false_bb = self.builder.get_insert_block().unwrap();
+                self.builder.build_unconditional_branch(done_bb);
+
+                self.builder.position_at_end(done_bb);
+                let tag = self.builder.build_phi(context.i8_type(), "ternary_tag");
+                tag.add_incoming(&[(&true_tag, true_bb), (&false_tag, false_bb)]);
+                let value = self.builder.build_phi(context.f64_type(), "ternary_value");
+                value.add_incoming(&[(&true_value, true_bb), (&false_value, false_bb)]);
+                self.alloc(tag.as_basic_value().into_int_value(), value.as_basic_value().into_float_value(), context)
+            }
+            Expr::Not(expr) => {
+                let truthy = self.compile_to_bool(expr, context);
+                let not = self.builder.build_not(truthy, "not");
+                self.bool_to_value(not, context)
+            }
+            Expr::UnaryMinus(operand) | Expr::UnaryPlus(operand) => {
+                let value = self.compile_expr(operand, context);
+                let (mut number, _) = self.build_to_number(value, context);
+                self.free_if_temp(operand, value);
+                if let Expr::UnaryMinus(_) = expr {
+                    number = self.builder.build_float_neg(number, "negate");
+                }
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), number, context)
+            }
+            Expr::PreIncrement(lvalue, delta) | Expr::PostIncrement(lvalue, delta) => {
+                let delta = context.f64_type().const_float(*delta);
+                let (old, new) = self.build_update(lvalue, &BinOp::Plus, delta, context);
+                let result = if let Expr::PreIncrement(_, _) = expr { new } else { old };
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Expr::Call(name, args) => self.compile_call(name, args, context),
+            Expr::CallBuiltin(builtin, args) => self.compile_builtin(*builtin, args, context),
+            Expr::Getline(source, lvalue) => self.compile_getline(source, lvalue.as_deref(), context),
+            Expr::NextLine => {
                 let next_line_res = self.builder.build_call(self.types.next_line, &[], "get_next_line").as_any_value_enum().into_float_value();
                 self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), next_line_res, context)
             }
         }
     }
 
+    fn compile_builtin(&mut self, builtin: Builtin, args: &[Expr], context: &'ctx Context) -> ValueT<'ctx> {
+        match builtin {
+            Builtin::Sprintf => {
+                if args.is_empty() {
+                    panic!("sprintf requires a format string");
+                }
+                let result = self.build_format_call(self.types.sprintf, None, &args[0], &args[1..], context);
+                let result = result.as_any_value_enum().into_float_value();
+                self.alloc(context.i8_type().const_int(STRING_TAG as u64, false), result, context)
+            }
+            Builtin::Close => {
+                let result = self.call_runtime(self.types.close_stream, args, context);
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Fflush => {
+                let result = if args.is_empty() {
+                    self.builder.build_call(self.types.flush_all, &[], "fflush").as_any_value_enum().into_float_value()
+                } else {
+                    self.call_runtime(self.types.flush_output, args, context)
+                };
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Length => {
+                // length with no argument is length($0)
+                let line = [Expr::Column(Box::new(Expr::NumberF64(0.0)))];
+                let args = if args.is_empty() { &line[..] } else { args };
+                let result = self.call_runtime(self.types.length, args, context);
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Substr => {
+                let mut args = args.to_vec();
+                if args.len() == 2 {
+                    args.push(Expr::NumberF64(f64::INFINITY));
+                }
+                let result = self.call_runtime(self.types.substr, &args, context);
+                self.alloc(context.i8_type().const_int(STRING_TAG as u64, false), result, context)
+            }
+            Builtin::Index => {
+                let result = self.call_runtime(self.types.index, args, context);
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Tolower | Builtin::Toupper => {
+                let function = if builtin == Builtin::Tolower { self.types.to_lower } else { self.types.to_upper };
+                let result = self.call_runtime(function, args, context);
+                self.alloc(context.i8_type().const_int(STRING_TAG as u64, false), result, context)
+            }
+            Builtin::Split => {
+                let name = match &args[1] {
+                    Expr::Variable(name) => name,
+                    _ => panic!("The second argument to split must be an array name"),
+                };
+                let array = self.lookup_array(name);
+                let string = self.compile_expr(&args[0], context);
+                let (sep, mode) = match args.get(2) {
+                    None => (self.create_value(Value::ConstString(String::new()), context), 0),
+                    Some(Expr::Regex(_)) => (self.compile_regex_arg(&args[2], context), 2),
+                    Some(sep) => (self.compile_expr(sep, context), 1),
+                };
+                let mut ffi_args = self.value_for_ffi(string);
+                ffi_args.push(array.into());
+                ffi_args.extend(self.value_for_ffi(sep));
+                ffi_args.push(context.i32_type().const_int(mode, false).into());
+                let result = self.builder.build_call(self.types.split, &ffi_args, "split").as_any_value_enum().into_float_value();
+                self.free_if_temp(&args[0], string);
+                if let Some(sep_expr) = args.get(2) {
+                    self.free_if_temp(sep_expr, sep);
+                }
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Sub | Builtin::Gsub => self.compile_substitute(builtin == Builtin::Gsub, args, context),
+            Builtin::Int | Builtin::Sqrt | Builtin::Exp | Builtin::Log | Builtin::Sin | Builtin::Cos => {
+                let function = match builtin {
+                    // int truncates toward zero
+                    Builtin::Int => self.types.trunc,
+                    Builtin::Sqrt => self.types.sqrt,
+                    Builtin::Exp => self.types.exp,
+                    Builtin::Log => self.types.log,
+                    Builtin::Sin => self.types.sin,
+                    _ => self.types.cos,
+                };
+                let number = self.compile_number(&args[0], context);
+                let result = self.builder.build_call(function, &[number.into()], builtin.name()).as_any_value_enum().into_float_value();
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Atan2 => {
+                let y = self.compile_number(&args[0], context);
+                let x = self.compile_number(&args[1], context);
+                let result = self.builder.build_call(self.types.atan2, &[y.into(), x.into()], "atan2").as_any_value_enum().into_float_value();
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Rand => {
+                let result = self.builder.build_call(self.types.rand, &[], "rand").as_any_value_enum().into_float_value();
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Srand => {
+                // srand() seeds from the time of day. Both return the previous seed.
+                let result = if args.is_empty() {
+                    self.builder.build_call(self.types.srand_time, &[], "srand").as_any_value_enum().into_float_value()
+                } else {
+                    self.call_runtime(self.types.srand, args, context)
+                };
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+            }
+            Builtin::Match => {
+                // The runtime sets RSTART and RLENGTH
+                let string = self.compile_expr(&args[0], context);
+                let regex = self.compile_regex_arg(&args[1], context);
+                let mut ffi_args = self.value_for_ffi(string);
+                ffi_args.extend(self.value_for_ffi(regex));
+                let start = self.builder.build_call(self.types.match_string, &ffi_args, "match").as_any_value_enum().into_float_value();
+                self.free_if_temp(&args[0], string);
+                self.free_if_temp(&args[1], regex);
+                self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), start, context)
+            }
+        }
+    }
+
+    // Calls a runtime function with the tag and value of each argument then frees the arguments.
+    // Returns the function's f64 result.
+    fn call_runtime(&mut self, function: FunctionValue<'ctx>, args: &[Expr], context: &'ctx Context) -> FloatValue<'ctx> {
+        let compiled: Vec<ValueT<'ctx>> = args.iter().map(|arg| self.compile_expr(arg, context)).collect();
+        let mut ffi_args = vec![];
+        for value in compiled.iter() {
+            ffi_args.extend(self.value_for_ffi(*value));
+        }
+        let result = self.builder.build_call(function, &ffi_args, "builtin").as_any_value_enum().into_float_value();
+        for (arg, value) in args.iter().zip(compiled) {
+            self.free_if_temp(arg, value);
+        }
+        result
+    }
+
+    // Compiles the expression and converts it to a number, freeing any temporary string
+    fn compile_number(&mut self, expr: &Expr, context: &'ctx Context) -> FloatValue<'ctx> {
+        let value = self.compile_expr(expr, context);
+        let (number, _) = self.build_to_number(value, context);
+        self.free_if_temp(expr, value);
+        number
+    }
+
+    // A regex literal passed to ~, split, sub, gsub or match is the pattern itself, not `$0 ~ /regex/`
+    fn compile_regex_arg(&mut self, expr: &Expr, context: &'ctx Context) -> ValueT<'ctx> {
+        match expr {
+            Expr::Regex(regex) => self.create_value(Value::ConstString(regex.clone()), context),
+            _ => self.compile_expr(expr, context),
+        }
+    }
+
+    // sub(regex, replacement, target) and gsub. The target defaults to $0 and is only assigned
+    // when something was replaced. Evaluates to the number of replacements.
+    fn compile_substitute(&mut self, global: bool, args: &[Expr], context: &'ctx Context) -> ValueT<'ctx> {
+        let target = match args.get(2) {
+            None => LValue::Column(Expr::NumberF64(0.0)),
+            Some(Expr::Variable(name)) => LValue::Variable(name.clone()),
+            Some(Expr::Column(col)) => LValue::Column((**col).clone()),
+            Some(Expr::ArrayIndex(name, indices)) => LValue::ArrayIndex(name.clone(), indices.clone()),
+            Some(other) => panic!("Cannot assign to {}", other),
+        };
+        let regex = self.compile_regex_arg(&args[0], context);
+        let replacement = self.compile_expr(&args[1], context);
+        let float_tag = context.i8_type().const_int(FLOAT_TAG as u64, false);
+        // The field index or subscript is evaluated once and used for both the read and the write
+        let index = self.compile_lvalue_index(&target, context);
+        let current = match (&target, index) {
+            (LValue::Column(_), Some(idx)) => {
+                let current = self.alloc(float_tag, context.f64_type().const_float(0.0), context);
+                let mut args = self.value_for_ffi(idx);
+                args.push(current.0.into());
+                args.push(current.1.into());
+                self.builder.build_call(self.types.column, &args, "get_column");
+                current
+            }
+            (LValue::ArrayIndex(name, _), Some(key)) => {
+                let array = self.lookup_array(name);
+                let current = self.alloc(float_tag, context.f64_type().const_float(0.0), context);
+                let mut args: Vec<BasicMetadataValueEnum> = vec![array.into()];
+                args.extend(self.value_for_ffi(key));
+                args.push(current.0.into());
+                args.push(current.1.into());
+                self.builder.build_call(self.types.array_get, &args, "array_get");
+                current
+            }
+            (LValue::Variable(name), _) => self.compile_expr(&Expr::Variable(name.clone()), context),
+            _ => unreachable!(),
+        };
+        let result = self.alloc(float_tag, context.f64_type().const_float(0.0), context);
+        let mut ffi_args = self.value_for_ffi(regex);
+        ffi_args.extend(self.value_for_ffi(replacement));
+        ffi_args.extend(self.value_for_ffi(current));
+        ffi_args.push(context.i32_type().const_int(global as u64, false).into());
+        ffi_args.push(result.0.into());
+        ffi_args.push(result.1.into());
+        let count = self.builder.build_call(self.types.substitute, &ffi_args, "substitute").as_any_value_enum().into_float_value();
+        self.free_if_temp(&args[0], regex);
+        self.free_if_temp(&args[1], replacement);
+        if index.is_some() {
+            let current_args = self.value_for_ffi(current);
+            self.builder.build_call(self.subroutines.free_if_string, &current_args, "free-target");
+        }
+
+        let assign_bb = context.append_basic_block(self.function(), "substitute_assign");
+        let done_bb = context.append_basic_block(self.function(), "substitute_done");
+        let replaced = self.builder.build_float_compare(FloatPredicate::OGT, count, context.f64_type().const_float(0.0), "replaced");
+        self.builder.build_conditional_branch(replaced, assign_bb, done_bb);
+        self.builder.position_at_end(assign_bb);
+        self.store_owned_at(&target, index, result);
+        self.builder.build_unconditional_branch(done_bb);
+        self.builder.position_at_end(done_bb);
+        self.free_lvalue_index(&target, index);
+        self.alloc(float_tag, count, context)
+    }
+
+    // The stream print or printf writes to. Null means stdout, otherwise the runtime opens the
+    // file or command on first use and keeps it open until close() or the end of the program.
+    fn compile_redirect(&mut self, redirect: Option<&Redirect>, context: &'ctx Context) -> PointerValue<'ctx> {
+        let (target, mode) = match redirect {
+            None => return context.i8_type().ptr_type(AddressSpace::Generic).const_null(),
+            Some(Redirect::Truncate(target)) => (target, 0),
+            Some(Redirect::Append(target)) => (target, 1),
+            Some(Redirect::Pipe(target)) => (target, 2),
+        };
+        let name = self.compile_expr(target, context);
+        let mut args = self.value_for_ffi(name);
+        args.push(context.i32_type().const_int(mode, false).into());
+        let stream = self.builder.build_call(self.types.output_stream, &args, "output_stream").as_any_value_enum().into_pointer_value();
+        self.free_if_temp(target, name);
+        stream
+    }
+
+    // printf and sprintf take the format plus stack arrays holding the tag and value of each argument.
+    // printf also takes the stream to write to first.
+    fn build_format_call(&mut self, function: FunctionValue<'ctx>, stream: Option<PointerValue<'ctx>>, format: &Expr, args: &[Expr], context: &'ctx Context) -> CallSiteValue<'ctx> {
+        let fmt = self.compile_expr(format, context);
+        let (array_args, compiled) = self.build_value_arrays(args, context);
+        let mut call_args: Vec<BasicMetadataValueEnum> = stream.into_iter().map(|s| s.into()).collect();
+        call_args.extend(self.value_for_ffi(fmt));
+        call_args.extend(array_args);
+        let result = self.builder.build_call(function, &call_args, "format");
+        self.free_if_temp(format, fmt);
+        for (arg, value) in compiled {
+            self.free_if_temp(arg, value);
+        }
+        result
+    }
+
+    // Compiles each expr into stack arrays of tags and values. Returns the (tags, values, count) args
+    // for the runtime and the compiled values, which the caller must free once the call is made.
+    fn build_value_arrays<'a>(&mut self, args: &'a [Expr], context: &'ctx Context) -> (Vec<BasicMetadataValueEnum<'ctx>>, Vec<(&'a Expr, ValueT<'ctx>)>) {
+        let count = args.len().max(1) as u32;
+        let tags = self.build_entry_alloca(context.i8_type().array_type(count), "tags", context);
+        let values = self.build_entry_alloca(context.f64_type().array_type(count), "values", context);
+        let zero = context.i32_type().const_int(0, false);
+
+        let mut compiled = vec![];
+        for (idx, arg) in args.iter().enumerate() {
+            let value = self.compile_expr(arg, context);
+            let (tag, val) = self.load(value);
+            let idx = context.i32_type().const_int(idx as u64, false);
+            let (tag_ptr, value_ptr) = unsafe {
+                (self.builder.build_in_bounds_gep(tags, &[zero, idx], "tag_ptr"),
+                 self.builder.build_in_bounds_gep(values, &[zero, idx], "value_ptr"))
+            };
+            self.builder.build_store(tag_ptr, tag);
+            self.builder.build_store(value_ptr, val);
+            compiled.push((arg, value));
+        }
+        let (tags, values) = unsafe {
+            (self.builder.build_in_bounds_gep(tags, &[zero, zero], "tags"),
+             self.builder.build_in_bounds_gep(values, &[zero, zero], "values"))
+        };
+        let count = context.i32_type().const_int(args.len() as u64, false);
+        (vec![tags.into(), values.into(), count.into()], compiled)
+    }
+
+    // Compiles an expression whose value is thrown away eg. `x = 1;` or `i++` in a for loop
+    fn compile_unused(&mut self, expr: &Expr, context: &'ctx Context) {
+        if let Expr::Assign(lvalue, value) = expr {
+            // Skip copying the value of the assignment only to free it
+            self.compile_assign(lvalue, value, false, context);
+            return;
+        }
+        let result = self.compile_expr(expr, context);
+        self.free_if_temp(expr, result);
+    }
+
+    // Stores the value of expr in lvalue. When `result` is set also returns an owned copy of the
+    // assigned value, which is the value of the assignment expression.
+    fn compile_assign(&mut self, lvalue: &LValue, expr: &Expr, result: bool, context: &'ctx Context) -> Option<ValueT<'ctx>> {
+        match lvalue {
+            LValue::Variable(name) => {
+                // Variables own their strings so one read from another variable must be copied
+                let (fin_tag, fin_value) = self.compile_owned(expr, context);
+                let existing = self.scopes.lookup(name).unwrap_or_else(|| panic!("Undefined variable {}", name));
+                let args = self.value_for_ffi(existing);
+                self.builder.build_call(self.subroutines.free_if_string, &args, "call-free-if-str");
+                self.builder.build_store(existing.0, fin_tag);
+                self.builder.build_store(existing.1, fin_value);
+                self.variable_assigned(name);
+                if result { Some(self.build_copy(existing, context)) } else { None }
+            }
+            LValue::Column(index) => {
+                let fin = self.compile_expr(expr, context);
+                let idx = self.compile_expr(index, context);
+                let mut args = self.value_for_ffi(idx);
+                args.extend(self.value_for_ffi(fin));
+                self.builder.build_call(self.types.set_column, &args, "set_column");
+                self.free_if_temp(index, idx);
+                let copy = if result { Some(self.build_copy(fin, context)) } else { None };
+                self.free_if_temp(expr, fin);
+                copy
+            }
+            LValue::ArrayIndex(name, indices) => {
+                let fin = self.compile_expr(expr, context);
+                let array = self.lookup_array(name);
+                let key = self.compile_subscript(indices, context);
+                let mut args: Vec<BasicMetadataValueEnum> = vec![array.into()];
+                args.extend(self.value_for_ffi(key));
+                args.extend(self.value_for_ffi(fin));
+                self.builder.build_call(self.types.array_set, &args, "array_set");
+                self.free_subscript(indices, key);
+                let copy = if result { Some(self.build_copy(fin, context)) } else { None };
+                self.free_if_temp(expr, fin);
+                copy
+            }
+        }
+    }
+
+    // Returns getline's result as a number: 1 when a record was read, 0 at the end of the input
+    // and -1 if the file or command couldn't be opened.
+    fn compile_getline(&mut self, source: &GetlineSource, lvalue: Option<&LValue>, context: &'ctx Context) -> ValueT<'ctx> {
+        let (function, name) = match source {
+            GetlineSource::Main => (self.types.getline_main, None),
+            GetlineSource::File(name) => (self.types.getline_file, Some(name)),
+            GetlineSource::Command(name) => (self.types.getline_command, Some(name)),
+        };
+        let compiled_name = name.map(|name| (name, self.compile_expr(name, context)));
+        let mut args = match compiled_name {
+            Some((_, value)) => self.value_for_ffi(value),
+            None => vec![],
+        };
+        let result = match lvalue {
+            None => {
+                args.push(context.i8_type().ptr_type(AddressSpace::Generic).const_null().into());
+                args.push(context.f64_type().ptr_type(AddressSpace::Generic).const_null().into());
+                self.builder.build_call(function, &args, "getline").as_any_value_enum().into_float_value()
+            }
+            Some(lvalue) => {
+                let line = self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), context.f64_type().const_float(0.0), context);
+                args.push(line.0.into());
+                args.push(line.1.into());
+                let result = self.builder.build_call(function, &args, "getline").as_any_value_enum().into_float_value();
+
+                // The lvalue is only assigned when a record was read
+                let assign_bb = context.append_basic_block(self.function(), "getline_assign");
+                let done_bb = context.append_basic_block(self.function(), "getline_done");
+                let read = self.builder.build_float_compare(FloatPredicate::OGT, result, context.f64_type().const_float(0.0), "getline_read");
+                self.builder.build_conditional_branch(read, assign_bb, done_bb);
+                self.builder.position_at_end(assign_bb);
+                self.store_owned(lvalue, line, context);
+                self.builder.build_unconditional_branch(done_bb);
+                self.builder.position_at_end(done_bb);
+                result
+            }
+        };
+        if let Some((name, value)) = compiled_name {
+            self.free_if_temp(name, value);
+        }
+        self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+    }
+
+    // Moves an owned value into lvalue
+    fn store_owned(&mut self, lvalue: &LValue, value: ValueT<'ctx>, context: &'ctx Context) {
+        let index = self.compile_lvalue_index(lvalue, context);
+        self.store_owned_at(lvalue, index, value);
+        self.free_lvalue_index(lvalue, index);
+    }
+
+    // The field index of a column or the key of an array element. None for a variable.
+    fn compile_lvalue_index(&mut self, lvalue: &LValue, context: &'ctx Context) -> Option<ValueT<'ctx>> {
+        match lvalue {
+            LValue::Variable(_) => None,
+            LValue::Column(index) => Some(self.compile_expr(index, context)),
+            LValue::ArrayIndex(_, indices) => Some(self.compile_subscript(indices, context)),
+        }
+    }
+
+    fn free_lvalue_index(&mut self, lvalue: &LValue, index: Option<ValueT<'ctx>>) {
+        match (lvalue, index) {
+            (LValue::Column(col), Some(idx)) => self.free_if_temp(col, idx),
+            (LValue::ArrayIndex(_, indices), Some(key)) => self.free_subscript(indices, key),
+            _ => {}
+        }
+    }
+
+    // Moves an owned value into lvalue using an index from compile_lvalue_index
+    fn store_owned_at(&mut self, lvalue: &LValue, index: Option<ValueT<'ctx>>, value: ValueT<'ctx>) {
+        match (lvalue, index) {
+            (LValue::Variable(name), _) => {
+                let existing = self.scopes.lookup(name).unwrap_or_else(|| panic!("Undefined variable {}", name));
+                let args = self.value_for_ffi(existing);
+                self.builder.build_call(self.subroutines.free_if_string, &args, "call-free-if-str");
+                let (tag, value) = self.load(value);
+                self.builder.build_store(existing.0, tag);
+                self.builder.build_store(existing.1, value);
+                self.variable_assigned(name);
+            }
+            (LValue::Column(_), Some(idx)) => {
+                let mut args = self.value_for_ffi(idx);
+                args.extend(self.value_for_ffi(value));
+                self.builder.build_call(self.types.set_column, &args, "set_column");
+                let args = self.value_for_ffi(value);
+                self.builder.build_call(self.subroutines.free_if_string, &args, "free-value");
+            }
+            (LValue::ArrayIndex(name, _), Some(key)) => {
+                let array = self.lookup_array(name);
+                let mut args: Vec<BasicMetadataValueEnum> = vec![array.into()];
+                args.extend(self.value_for_ffi(key));
+                args.extend(self.value_for_ffi(value));
+                self.builder.build_call(self.types.array_set, &args, "array_set");
+                let args = self.value_for_ffi(value);
+                self.builder.build_call(self.subroutines.free_if_string, &args, "free-value");
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Compiles expr and returns its tag and value, copying the string if it belongs to a variable
+    fn compile_owned(&mut self, expr: &Expr, context: &'ctx Context) -> (IntValue<'ctx>, FloatValue<'ctx>) {
+        let value = self.compile_expr(expr, context);
+        let (tag, mut value) = self.load(value);
+        if let Expr::Variable(_) = expr {
+            value = self.builder.build_call(self.types.copy_if_string, &[tag.into(), value.into()], "copy_if_string").as_any_value_enum().into_float_value();
+        }
+        (tag, value)
+    }
+
+    fn build_copy(&mut self, value: ValueT<'ctx>, context: &'ctx Context) -> ValueT<'ctx> {
+        let (tag, value) = self.load(value);
+        let copy = self.builder.build_call(self.types.copy_if_string, &[tag.into(), value.into()], "copy_if_string").as_any_value_enum().into_float_value();
+        self.alloc(tag, copy, context)
+    }
+
+    // Applies `lvalue = lvalue op operand` for compound assignment and ++/--. Subscripts are only
+    // evaluated once. Returns the numeric value before and after the update.
+    fn build_update(&mut self, lvalue: &LValue, op: &BinOp, operand: FloatValue<'ctx>, context: &'ctx Context) -> (FloatValue<'ctx>, FloatValue<'ctx>) {
+        let float_tag = context.i8_type().const_int(FLOAT_TAG as u64, false);
+        match lvalue {
+            LValue::Variable(name) => {
+                let existing = self.scopes.lookup(name).expect("to be defined");
+                let (old, _) = self.build_to_number(existing, context);
+                let new = self.build_arith(old, operand, op);
+                let args = self.value_for_ffi(existing);
+                self.builder.build_call(self.subroutines.free_if_string, &args, "call-free-if-str");
+                self.builder.build_store(existing.0, float_tag);
+                self.builder.build_store(existing.1, new);
+                self.variable_assigned(name);
+                (old, new)
+            }
+            LValue::Column(index) => {
+                let idx = self.compile_expr(index, context);
+                let current = self.alloc(float_tag, context.f64_type().const_float(0.0), context);
+                let mut args = self.value_for_ffi(idx);
+                args.push(current.0.into());
+                args.push(current.1.into());
+                self.builder.build_call(self.types.column, &args, "get_column");
+                let (old, _) = self.build_to_number(current, context);
+                let current_args = self.value_for_ffi(current);
+                self.builder.build_call(self.subroutines.free_if_string, &current_args, "free-column");
+                let new = self.build_arith(old, operand, op);
+                let mut args = self.value_for_ffi(idx);
+                args.push(float_tag.into());
+                args.push(new.into());
+                self.builder.build_call(self.types.set_column, &args, "set_column");
+                self.free_if_temp(index, idx);
+                (old, new)
+            }
+            LValue::ArrayIndex(name, indices) => {
+                let array = self.lookup_array(name);
+                let key = self.compile_subscript(indices, context);
+                let current = self.alloc(float_tag, context.f64_type().const_float(0.0), context);
+                let mut args: Vec<BasicMetadataValueEnum> = vec![array.into()];
+                args.extend(self.value_for_ffi(key));
+                args.push(current.0.into());
+                args.push(current.1.into());
+                self.builder.build_call(self.types.array_get, &args, "array_get");
+                let (old, _) = self.build_to_number(current, context);
+                let current_args = self.value_for_ffi(current);
+                self.builder.build_call(self.subroutines.free_if_string, &current_args, "free-element");
+                let new = self.build_arith(old, operand, op);
+                let mut args: Vec<BasicMetadataValueEnum> = vec![array.into()];
+                args.extend(self.value_for_ffi(key));
+                args.push(float_tag.into());
+                args.push(new.into());
+                self.builder.build_call(self.types.array_set, &args, "array_set");
+                self.free_subscript(indices, key);
+                (old, new)
+            }
+        }
+    }
+
+    fn build_arith(&mut self, left_float: FloatValue<'ctx>, right_float: FloatValue<'ctx>, op: &BinOp) -> FloatValue<'ctx> {
+        let name = "arith";
+        match op {
+            BinOp::Minus => self.builder.build_float_sub(left_float, right_float, name),
+            BinOp::Plus => self.builder.build_float_add(left_float, right_float, name),
+            BinOp::Slash => self.builder.build_float_div(left_float, right_float, name),
+            BinOp::Star => self.builder.build_float_mul(left_float, right_float, name),
+            // frem has the same semantics as C's fmod which is what awk specifies for %
+            BinOp::Modulo => self.builder.build_float_rem(left_float, right_float, name),
+            BinOp::Pow => self.builder.build_call(self.types.pow, &[left_float.into(), right_float.into()], name).as_any_value_enum().into_float_value(),
+            _ => panic!("only arithmetic"),
+        }
+    }
+
+    // Returns 1.0 if the string value of `value` matches the regex `regex` else 0.0
+    fn build_match(&mut self, value: ValueT<'ctx>, regex: ValueT<'ctx>) -> FloatValue<'ctx> {
+        let mut args = self.value_for_ffi(value);
+        args.extend(self.value_for_ffi(regex));
+        self.builder.build_call(self.types.match_regex, &args, "match_regex").as_any_value_enum().into_float_value()
+    }
+
+    // Lets the runtime react to assignments to special variables that change the record
+    fn variable_assigned(&mut self, name: &str) {
+        if name == "NF" {
+            self.builder.build_call(self.types.assign_nf, &[], "assign_nf");
+        }
+    }
+
+    // Two numbers are compared inline. Anything else goes to the runtime which decides between a
+    // numeric and a string comparison.
+    fn compile_compare(&mut self, left: &Expr, op: &BinOp, right: &Expr, context: &'ctx Context) -> ValueT<'ctx> {
+        let l = self.compile_expr(left, context);
+        let r = self.compile_expr(right, context);
+        let (l_tag, l_value) = self.load(l);
+        let (r_tag, r_value) = self.load(r);
+
+        let function = self.function();
+        let numbers_bb = context.append_basic_block(function, "compare_numbers");
+        let runtime_bb = context.append_basic_block(function, "compare_runtime");
+        let done_bb = context.append_basic_block(function, "compare_done");
+        let tags = self.builder.build_or(l_tag, r_tag, "compare_tags");
+        let both_numbers = self.builder.build_int_compare(IntPredicate::EQ, tags, context.i8_type().const_int(FLOAT_TAG as u64, false), "both_numbers");
+        self.builder.build_conditional_branch(both_numbers, numbers_bb, runtime_bb);
+
+        self.builder.position_at_end(numbers_bb);
+        let compared = self.builder.build_float_compare(op.predicate(), l_value, r_value, "compare");
+        let number_result = self.builder.build_unsigned_int_to_float(compared, context.f64_type(), "compare_float");
+        self.builder.build_unconditional_branch(done_bb);
+
+        self.builder.position_at_end(runtime_bb);
+        let op_code = match op {
+            BinOp::Less => 0,
+            BinOp::LessEq => 1,
+            BinOp::EqEq => 2,
+            BinOp::BangEq => 3,
+            BinOp::GreaterEq => 4,
+            _ => 5,
+        };
+        let args = [l_tag.into(), l_value.into(), r_tag.into(), r_value.into(), context.i32_type().const_int(op_code, false).into()];
+        let runtime_result = self.builder.build_call(self.types.compare_values, &args, "compare_values").as_any_value_enum().into_float_value();
+        self.builder.build_unconditional_branch(done_bb);
+
+        self.builder.position_at_end(done_bb);
+        let phi = self.builder.build_phi(context.f64_type(), "compare_result");
+        phi.add_incoming(&[(&number_result, numbers_bb), (&runtime_result, runtime_bb)]);
+        let result = phi.as_basic_value().into_float_value();
+        self.free_if_temp(left, l);
+        self.free_if_temp(right, r);
+        self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), result, context)
+    }
+
+    // Values produced by compile_expr are owned temporaries unless they are variables.
+    // Frees the temporary string (if any) once the caller is done with it.
+    fn free_if_temp(&mut self, expr: &Expr, value: ValueT<'ctx>) {
+        if let Expr::Variable(_) = expr {
+            return;
+        }
+        let args = self.value_for_ffi(value);
+        self.builder.build_call(self.subroutines.free_if_string, &args, "free-temp");
+    }
+
     #[allow(dead_code)]
     fn cast_float_to_int(&self, float: FloatValue<'ctx>, context: &'ctx Context) -> IntValue<'ctx> {
         self.builder.build_bitcast::<IntType, FloatValue>(
@@ -324,7 +1570,7 @@ impl<'ctx> CodeGen<'ctx> {
 
     fn cast_int_to_float(&self, int: IntValue<'ctx>, context: &'ctx Context) -> FloatValue<'ctx> {
         self.builder.build_bitcast::<FloatType, IntValue>(
-            int, context.f64_type().into(), "cast-int-to-float").into_float_value()
+            int, context.f64_type(), "cast-int-to-float").into_float_value()
     }
 
     fn cast_ptr_to_float(&self, ptr: PointerValue<'ctx>, context: &'ctx Context) -> FloatValue<'ctx> {
@@ -382,12 +1628,11 @@ impl<'ctx> CodeGen<'ctx> {
     // }
 
     fn build_to_number(&mut self, value_ptrs: ValueT<'ctx>, context: &'ctx Context) -> (FloatValue<'ctx>, BasicBlock<'ctx>) {
-        // Get ROOT function
-        let root = self.module.get_function(ROOT).expect("root to exist");
+        let function = self.function();
         // Basic blocks for not number and number
         let init_bb = self.builder.get_insert_block().unwrap();
-        let not_number_bb = context.append_basic_block(root, "not_number");
-        let done_bb = context.append_basic_block(root, "done_bb");
+        let not_number_bb = context.append_basic_block(function, "not_number");
+        let done_bb = context.append_basic_block(function, "done_bb");
 
         let (tag, value) = self.load(value_ptrs);
         let cmp = self.builder.build_int_compare(IntPredicate::EQ, context.i8_type().const_int(0, false), tag, "is_zero");
@@ -408,22 +1653,20 @@ impl<'ctx> CodeGen<'ctx> {
     fn build_f64_binop(&mut self, left_float: FloatValue<'ctx>, right_float: FloatValue<'ctx>, op: &BinOp, context: &'ctx Context) -> ValueT<'ctx> {
         let name = "both-f64-binop-tag";
         let res = match op {
-            BinOp::Minus => self.builder.build_float_sub(left_float, right_float, name),
-            BinOp::Plus => self.builder.build_float_add(left_float, right_float, name),
-            BinOp::Slash => self.builder.build_float_div(left_float, right_float, name),
-            BinOp::Star => self.builder.build_float_mul(left_float, right_float, name),
+            BinOp::Minus | BinOp::Plus | BinOp::Slash | BinOp::Star | BinOp::Modulo | BinOp::Pow => {
+                self.build_arith(left_float, right_float, op)
+            }
             BinOp::Greater | BinOp::Less | BinOp::GreaterEq |
             BinOp::LessEq | BinOp::EqEq | BinOp::BangEq => {
                 let predicate = op.predicate();
                 let result = self.builder.build_float_compare(predicate, left_float, right_float, name);
 
-                let result_tag = self.builder.build_alloca(context.i8_type(), &format!("{}_result", name));
-                let result_value = self.builder.build_alloca(context.f64_type(), &format!("{}_result", name));
+                let result_tag = self.build_entry_alloca(context.i8_type(), &format!("{}_result", name), context);
 
-                let root = self.module.get_function(ROOT).expect("root to exist");
-                let is_zero_bb = context.append_basic_block(root, "binop_zero");
-                let is_one_bb = context.append_basic_block(root, "binop_one");
-                let continue_bb = context.append_basic_block(root, "binop_cont");
+                let function = self.function();
+                let is_zero_bb = context.append_basic_block(function, "binop_zero");
+                let is_one_bb = context.append_basic_block(function, "binop_one");
+                let continue_bb = context.append_basic_block(function, "binop_cont");
 
                 self.builder.build_store(result_tag, context.i8_type().const_int(FLOAT_TAG as u64, false));
 
@@ -440,8 +1683,7 @@ impl<'ctx> CodeGen<'ctx> {
                 phi.add_incoming(&[(&context.f64_type().const_float(0.0), is_zero_bb), (&context.f64_type().const_float(1.0), is_one_bb)]);
                 phi.as_basic_value().into_float_value()
             }
-
-            _ => panic!("only arithmetic")
+            BinOp::Matches | BinOp::NotMatches => panic!("only arithmetic"),
         };
         self.alloc(context.i8_type().const_int(FLOAT_TAG as u64, false), res, context)
         // (context.i8_type().const_int(FLOAT_TAG as u64, false), res)
@@ -1,20 +1,41 @@
 use std::collections::HashMap;
+use inkwell::values::PointerValue;
 use crate::codgen::ValueT;
 
 pub type ScopeInfo<'ctx> = HashMap<String, ValueT<'ctx>>;
 
+// Variables kept by the runtime. They are resolved before any user variable so nothing can
+// shadow them.
+pub const SPECIAL_SCALARS: &[&str] = &["NR", "FNR", "NF", "FILENAME", "RSTART", "RLENGTH", "SUBSEP", "ARGC", "FS", "RS", "RT",
+    "CONVFMT", "OFMT", "OFS"];
+pub const SPECIAL_ARRAYS: &[&str] = &["ENVIRON", "ARGV"];
+
+pub fn is_special(name: &str) -> bool {
+    SPECIAL_SCALARS.contains(&name) || SPECIAL_ARRAYS.contains(&name)
+}
+
 pub struct Scope<'ctx> {
     pub values: ScopeInfo<'ctx>,
+    // Pointer to the slot holding the runtime's array pointer
+    pub arrays: HashMap<String, PointerValue<'ctx>>,
 }
 
 pub struct Scopes<'ctx> {
     scopes: Vec<Scope<'ctx>>,
+    specials: Scope<'ctx>,
 }
 
 impl<'ctx> Scopes<'ctx> {
     pub fn new() -> Self {
-        let scope = Scope { values: HashMap::default() };
-        Scopes { scopes: vec![scope] }
+        let scope = Scope { values: HashMap::default(), arrays: HashMap::default() };
+        let specials = Scope { values: HashMap::default(), arrays: HashMap::default() };
+        Scopes { scopes: vec![scope], specials }
+    }
+    pub fn insert_special(&mut self, name: String, value: ValueT<'ctx>) {
+        self.specials.values.insert(name, value);
+    }
+    pub fn insert_special_array(&mut self, name: String, array: PointerValue<'ctx>) {
+        self.specials.arrays.insert(name, array);
     }
     pub fn insert(&mut self, name: String, value: ValueT<'ctx>) {
         self.scopes.last_mut().unwrap().values.insert(name, value);
@@ -29,15 +50,32 @@ impl<'ctx> Scopes<'ctx> {
         None
     }
     pub fn begin_scope(&mut self) {
-        self.scopes.push(Scope { values: HashMap::default() });
+        self.scopes.push(Scope { values: HashMap::default(), arrays: HashMap::default() });
     }
     pub fn end_scope(&mut self) -> ScopeInfo<'ctx> {
         self.scopes.pop().unwrap().values
     }
+    pub fn insert_array(&mut self, name: String, array: PointerValue<'ctx>) {
+        self.scopes.last_mut().unwrap().arrays.insert(name, array);
+    }
+    pub fn lookup_array(&self, name: &str) -> Option<PointerValue<'ctx>> {
+        if let Some(array) = self.specials.arrays.get(name) {
+            return Some(*array);
+        }
+        for scope in self.scopes.iter().rev() {
+            if let Some(array) = scope.arrays.get(name) {
+                return Some(*array);
+            }
+        }
+        None
+    }
     pub fn lookup(&self, name: &str) -> Option<ValueT<'ctx>> {
+        if let Some(val) = self.specials.values.get(name) {
+            return Some(*val);
+        }
         for scope in self.scopes.iter().rev() {
             if let Some(val) = scope.values.get(name) {
-                return Some(val.clone());
+                return Some(*val);
             }
         }
         None
@@ -4,6 +4,7 @@ use inkwell::IntPredicate;
 use inkwell::module::Module;
 use inkwell::values::FunctionValue;
 use crate::codgen::types::Types;
+use crate::codgen::{STRING_TAG, STRNUM_TAG};
 
 pub struct Subroutines<'ctx> {
     pub free_if_string: FunctionValue<'ctx>,
@@ -28,7 +29,9 @@ impl<'ctx> Subroutines<'ctx> {
         builder.position_at_end(init_bb);
         let tag = free_if_string.get_nth_param(0).expect("to have 1 tag arg").into_int_value();
         let value = free_if_string.get_nth_param(1).expect("to have 1 value arg").into_float_value();
-        let tag_cmp = builder.build_int_compare(IntPredicate::EQ, tag, context.i8_type().const_int(1, false), "cmp_with_str_tag");
+        let is_string = builder.build_int_compare(IntPredicate::EQ, tag, context.i8_type().const_int(STRING_TAG as u64, false), "cmp_with_str_tag");
+        let is_strnum = builder.build_int_compare(IntPredicate::EQ, tag, context.i8_type().const_int(STRNUM_TAG as u64, false), "cmp_with_strnum_tag");
+        let tag_cmp = builder.build_or(is_string, is_strnum, "owns_string");
         builder.build_conditional_branch(tag_cmp, free_string_bb, ret_bb);
 
         // free the string
@@ -1,18 +1,68 @@
 use inkwell::AddressSpace;
 use inkwell::module::{Linkage, Module};
 use inkwell::values::FunctionValue;
-use crate::codgen::ValueT;
 
 pub struct Types<'ctx> {
     pub print: FunctionValue<'ctx>,
-    pub get_float: FunctionValue<'ctx>,
     pub next_line: FunctionValue<'ctx>,
     pub column: FunctionValue<'ctx>,
     pub add_file: FunctionValue<'ctx>,
     pub init: FunctionValue<'ctx>,
     pub free_string: FunctionValue<'ctx>,
     pub string_to_number: FunctionValue<'ctx>,
-    pub number_to_string: FunctionValue<'ctx>,
+    pub match_regex: FunctionValue<'ctx>,
+    pub set_column: FunctionValue<'ctx>,
+    pub copy_if_string: FunctionValue<'ctx>,
+    pub new_array: FunctionValue<'ctx>,
+    pub array_get: FunctionValue<'ctx>,
+    pub array_set: FunctionValue<'ctx>,
+    pub array_in: FunctionValue<'ctx>,
+    pub array_delete: FunctionValue<'ctx>,
+    pub array_clear: FunctionValue<'ctx>,
+    pub free_array: FunctionValue<'ctx>,
+    pub subsep_join: FunctionValue<'ctx>,
+    pub array_iter: FunctionValue<'ctx>,
+    pub array_iter_has_next: FunctionValue<'ctx>,
+    pub array_iter_next: FunctionValue<'ctx>,
+    pub array_iter_free: FunctionValue<'ctx>,
+    pub printf: FunctionValue<'ctx>,
+    pub sprintf: FunctionValue<'ctx>,
+    pub concat: FunctionValue<'ctx>,
+    pub string_truthy: FunctionValue<'ctx>,
+    pub pow: FunctionValue<'ctx>,
+    pub nextfile: FunctionValue<'ctx>,
+    pub next_outside_records: FunctionValue<'ctx>,
+    pub exit_program: FunctionValue<'ctx>,
+    pub getline_main: FunctionValue<'ctx>,
+    pub getline_file: FunctionValue<'ctx>,
+    pub getline_command: FunctionValue<'ctx>,
+    pub output_stream: FunctionValue<'ctx>,
+    pub close_stream: FunctionValue<'ctx>,
+    pub flush_output: FunctionValue<'ctx>,
+    pub flush_all: FunctionValue<'ctx>,
+    pub close_all_streams: FunctionValue<'ctx>,
+    pub length: FunctionValue<'ctx>,
+    pub substr: FunctionValue<'ctx>,
+    pub index: FunctionValue<'ctx>,
+    pub split: FunctionValue<'ctx>,
+    pub substitute: FunctionValue<'ctx>,
+    pub match_string: FunctionValue<'ctx>,
+    pub to_lower: FunctionValue<'ctx>,
+    pub to_upper: FunctionValue<'ctx>,
+    pub trunc: FunctionValue<'ctx>,
+    pub sqrt: FunctionValue<'ctx>,
+    pub exp: FunctionValue<'ctx>,
+    pub log: FunctionValue<'ctx>,
+    pub sin: FunctionValue<'ctx>,
+    pub cos: FunctionValue<'ctx>,
+    pub atan2: FunctionValue<'ctx>,
+    pub rand: FunctionValue<'ctx>,
+    pub srand: FunctionValue<'ctx>,
+    pub srand_time: FunctionValue<'ctx>,
+    pub assign_nf: FunctionValue<'ctx>,
+    pub string_with_nul: FunctionValue<'ctx>,
+    pub compare_values: FunctionValue<'ctx>,
+    pub allow_non_decimal_data: FunctionValue<'ctx>,
 }
 
 impl<'ctx> Types<'ctx> {
@@ -41,9 +91,9 @@ impl<'ctx> Types<'ctx> {
         let i8 = context.i8_type();
         let f64 = context.f64_type();
         let ret_void_arg_value = context.void_type().fn_type(&[i8.into(), f64.into()], false);
-        let get_float_type = f64.fn_type(&[], false);
         let next_line_type = context.f64_type().fn_type(&[], false);
         let column_type = context.f64_type().fn_type(&[i8.into(), f64.into()], false);
+        let binop_type = context.f64_type().fn_type(&[i8.into(), f64.into(), i8.into(), f64.into()], false);
         let mut message = String::new(); // to satisfy llvm types just pass everything as a 200 long vector
         for _i in 0..200 {
             message.push('1');
@@ -53,31 +103,163 @@ impl<'ctx> Types<'ctx> {
         let ptr_to_const_str_type = const_str_type.ptr_type(AddressSpace::Generic).into();
         let add_file_type = context.void_type().fn_type(&[ptr_to_const_str_type], false);
 
-        let print = module.add_function("print_value", ret_void_arg_value, Some(Linkage::ExternalWeak));
-        let get_float = module.add_function("get_float", get_float_type, Some(Linkage::ExternalWeak));
         let next_line = module.add_function("next_line", next_line_type, Some(Linkage::ExternalWeak));
-        let column = module.add_function("column", column_type, Some(Linkage::ExternalWeak));
+        // column writes the field's tag and value to the out pointers
+        let column_out_type = context.void_type().fn_type(&[i8.into(), f64.into(), i8.ptr_type(AddressSpace::Generic).into(), f64.ptr_type(AddressSpace::Generic).into()], false);
+        let column = module.add_function("column", column_out_type, Some(Linkage::ExternalWeak));
         let add_file = module.add_function("add_file", add_file_type, Some(Linkage::ExternalWeak));
         let init = module.add_function("init", context.void_type().fn_type(&[], false), Some(Linkage::ExternalWeak));
         let free_string = module.add_function("free_string", ret_void_arg_value, Some(Linkage::ExternalWeak));
         let string_to_number = module.add_function("string_to_number", column_type, Some(Linkage::ExternalWeak));
-        let number_to_string = module.add_function("number_to_string", column_type, Some(Linkage::ExternalWeak));
+        let match_regex = module.add_function("match_regex", binop_type, Some(Linkage::ExternalWeak));
+        let set_column_type = context.void_type().fn_type(&[i8.into(), f64.into(), i8.into(), f64.into()], false);
+        let set_column = module.add_function("set_column", set_column_type, Some(Linkage::ExternalWeak));
+        let copy_if_string = module.add_function("copy_if_string", column_type, Some(Linkage::ExternalWeak));
+
+        // Arrays and iterators are opaque pointers owned by the runtime
+        let ptr = i8.ptr_type(AddressSpace::Generic);
+        let new_array = module.add_function("new_array", ptr.fn_type(&[], false), Some(Linkage::ExternalWeak));
+        let array_get_type = context.void_type().fn_type(&[ptr.into(), i8.into(), f64.into(), ptr.into(), f64.ptr_type(AddressSpace::Generic).into()], false);
+        let array_get = module.add_function("array_get", array_get_type, Some(Linkage::ExternalWeak));
+        let array_set_type = context.void_type().fn_type(&[ptr.into(), i8.into(), f64.into(), i8.into(), f64.into()], false);
+        let array_set = module.add_function("array_set", array_set_type, Some(Linkage::ExternalWeak));
+        let array_in = module.add_function("array_in", f64.fn_type(&[ptr.into(), i8.into(), f64.into()], false), Some(Linkage::ExternalWeak));
+        let array_delete = module.add_function("array_delete", context.void_type().fn_type(&[ptr.into(), i8.into(), f64.into()], false), Some(Linkage::ExternalWeak));
+        let array_clear = module.add_function("array_clear", context.void_type().fn_type(&[ptr.into()], false), Some(Linkage::ExternalWeak));
+        let free_array = module.add_function("free_array", context.void_type().fn_type(&[ptr.into()], false), Some(Linkage::ExternalWeak));
+        let subsep_join = module.add_function("subsep_join", binop_type, Some(Linkage::ExternalWeak));
+        let array_iter = module.add_function("array_iter", ptr.fn_type(&[ptr.into()], false), Some(Linkage::ExternalWeak));
+        let array_iter_has_next = module.add_function("array_iter_has_next", f64.fn_type(&[ptr.into()], false), Some(Linkage::ExternalWeak));
+        let array_iter_next = module.add_function("array_iter_next", f64.fn_type(&[ptr.into()], false), Some(Linkage::ExternalWeak));
+        let array_iter_free = module.add_function("array_iter_free", context.void_type().fn_type(&[ptr.into()], false), Some(Linkage::ExternalWeak));
+        // print and printf take the output stream first, null for stdout
+        let print = module.add_function("print_value", context.void_type().fn_type(&[ptr.into(), i8.into(), f64.into()], false), Some(Linkage::ExternalWeak));
+        // format tag, format value, arg tags, arg values, arg count
+        let format_args = [i8.into(), f64.into(), ptr.into(), f64.ptr_type(AddressSpace::Generic).into(), context.i32_type().into()];
+        let sprintf = module.add_function("sprintf_values", f64.fn_type(&format_args, false), Some(Linkage::ExternalWeak));
+        let printf_args = [ptr.into(), format_args[0], format_args[1], format_args[2], format_args[3], format_args[4]];
+        let printf = module.add_function("printf_values", context.void_type().fn_type(&printf_args, false), Some(Linkage::ExternalWeak));
+        let concat_type = f64.fn_type(&[ptr.into(), f64.ptr_type(AddressSpace::Generic).into(), context.i32_type().into()], false);
+        let concat = module.add_function("concat_values", concat_type, Some(Linkage::ExternalWeak));
+        let string_truthy = module.add_function("string_truthy", column_type, Some(Linkage::ExternalWeak));
+        // LLVM intrinsic, lowered to a call to libm's pow
+        let pow = module.add_function("llvm.pow.f64", f64.fn_type(&[f64.into(), f64.into()], false), None);
+        let nextfile = module.add_function("nextfile", context.void_type().fn_type(&[], false), Some(Linkage::ExternalWeak));
+        let next_outside_records = module.add_function("next_outside_records", context.void_type().fn_type(&[], false), Some(Linkage::ExternalWeak));
+        // getline writes the record to the out pointers, or to $0 when they are null
+        let out_ptrs = [ptr.into(), f64.ptr_type(AddressSpace::Generic).into()];
+        let getline_main = module.add_function("getline_main", f64.fn_type(&out_ptrs, false), Some(Linkage::ExternalWeak));
+        let getline_named_type = f64.fn_type(&[i8.into(), f64.into(), out_ptrs[0], out_ptrs[1]], false);
+        let getline_file = module.add_function("getline_file", getline_named_type, Some(Linkage::ExternalWeak));
+        let getline_command = module.add_function("getline_command", getline_named_type, Some(Linkage::ExternalWeak));
+        let exit_program = module.add_function("exit_program", context.void_type().fn_type(&[context.i64_type().into()], false), Some(Linkage::ExternalWeak));
+        // name tag, name value, mode (0 for >, 1 for >>, 2 for |)
+        let output_stream = module.add_function("output_stream", ptr.fn_type(&[i8.into(), f64.into(), context.i32_type().into()], false), Some(Linkage::ExternalWeak));
+        let close_stream = module.add_function("close_stream", column_type, Some(Linkage::ExternalWeak));
+        let flush_output = module.add_function("flush_output", column_type, Some(Linkage::ExternalWeak));
+        let flush_all = module.add_function("flush_all", f64.fn_type(&[], false), Some(Linkage::ExternalWeak));
+        let close_all_streams = module.add_function("close_all_streams", context.void_type().fn_type(&[], false), Some(Linkage::ExternalWeak));
+        // String built-ins. Those returning strings return owned ones.
+        let length = module.add_function("length_value", column_type, Some(Linkage::ExternalWeak));
+        let substr = module.add_function("substr_value", f64.fn_type(&[i8.into(), f64.into(), i8.into(), f64.into(), i8.into(), f64.into()], false), Some(Linkage::ExternalWeak));
+        let index = module.add_function("index_of", binop_type, Some(Linkage::ExternalWeak));
+        // string tag, string value, array, separator tag, separator value, mode (0 for FS, 1 for a string, 2 for a regex)
+        let split_type = f64.fn_type(&[i8.into(), f64.into(), ptr.into(), i8.into(), f64.into(), context.i32_type().into()], false);
+        let split = module.add_function("split_values", split_type, Some(Linkage::ExternalWeak));
+        // regex, replacement, target, global, then out pointers for the new target
+        let substitute_type = f64.fn_type(&[i8.into(), f64.into(), i8.into(), f64.into(), i8.into(), f64.into(), context.i32_type().into(), out_ptrs[0], out_ptrs[1]], false);
+        let substitute = module.add_function("substitute", substitute_type, Some(Linkage::ExternalWeak));
+        let match_string = module.add_function("match_string", binop_type, Some(Linkage::ExternalWeak));
+        let to_lower = module.add_function("to_lower", column_type, Some(Linkage::ExternalWeak));
+        let to_upper = module.add_function("to_upper", column_type, Some(Linkage::ExternalWeak));
+        // Math built-ins. LLVM intrinsics where they exist so constant arguments get folded.
+        let unary_math_type = f64.fn_type(&[f64.into()], false);
+        let trunc = module.add_function("llvm.trunc.f64", unary_math_type, None);
+        let sqrt = module.add_function("llvm.sqrt.f64", unary_math_type, None);
+        let exp = module.add_function("llvm.exp.f64", unary_math_type, None);
+        let log = module.add_function("llvm.log.f64", unary_math_type, None);
+        let sin = module.add_function("llvm.sin.f64", unary_math_type, None);
+        let cos = module.add_function("llvm.cos.f64", unary_math_type, None);
+        // No intrinsic for atan2, this is libm's
+        let atan2 = module.add_function("atan2", f64.fn_type(&[f64.into(), f64.into()], false), None);
+        let rand = module.add_function("rand_value", f64.fn_type(&[], false), Some(Linkage::ExternalWeak));
+        let srand = module.add_function("srand_value", column_type, Some(Linkage::ExternalWeak));
+        let srand_time = module.add_function("srand_time", f64.fn_type(&[], false), Some(Linkage::ExternalWeak));
+        // Called after NF is stored to so the runtime can resize the record
+        let assign_nf = module.add_function("assign_nf", context.void_type().fn_type(&[], false), Some(Linkage::ExternalWeak));
+        // left tag, left value, right tag, right value, operator (0 <, 1 <=, 2 ==, 3 !=, 4 >=, 5 >)
+        let compare_type = f64.fn_type(&[i8.into(), f64.into(), i8.into(), f64.into(), context.i32_type().into()], false);
+        let compare_values = module.add_function("compare_values", compare_type, Some(Linkage::ExternalWeak));
+        let allow_non_decimal_data = module.add_function("allow_non_decimal_data", context.void_type().fn_type(&[], false), Some(Linkage::ExternalWeak));
+        // A string literal holding a NUL and its length
+        let string_with_nul = module.add_function("string_with_nul", context.void_type().fn_type(&[ptr.into(), context.i64_type().into()], false), Some(Linkage::ExternalWeak));
         Types {
             print,
-            get_float,
             next_line,
             column,
             init,
             free_string,
             string_to_number,
-            number_to_string,
             add_file,
+            match_regex,
+            set_column,
+            copy_if_string,
+            new_array,
+            array_get,
+            array_set,
+            array_in,
+            array_delete,
+            array_clear,
+            free_array,
+            subsep_join,
+            array_iter,
+            array_iter_has_next,
+            array_iter_next,
+            array_iter_free,
+            printf,
+            sprintf,
+            concat,
+            string_truthy,
+            pow,
+            nextfile,
+            next_outside_records,
+            exit_program,
+            getline_main,
+            getline_file,
+            getline_command,
+            output_stream,
+            close_stream,
+            flush_output,
+            flush_all,
+            close_all_streams,
+            length,
+            substr,
+            index,
+            split,
+            substitute,
+            match_string,
+            to_lower,
+            to_upper,
+            trunc,
+            sqrt,
+            exp,
+            log,
+            sin,
+            cos,
+            atan2,
+            rand,
+            srand,
+            srand_time,
+            assign_nf,
+            string_with_nul,
+            compare_values,
+            allow_non_decimal_data,
         }
     }
 }
 
 pub fn pad(path: &mut String) {
-    if path.as_bytes().len() > 200 { panic!("file path is too long {}", path)}
+    if path.len() > 200 { panic!("file path is too long {}", path)}
     while path.len() < 200 {
         path.push('\0');
     }
@@ -1,55 +1,270 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use crate::Expr;
-use crate::parser::{Stmt};
+use crate::codgen::scopes::{is_special, SPECIAL_ARRAYS, SPECIAL_SCALARS};
+use crate::parser::{Builtin, Function, GetlineSource, LValue, Redirect, Stmt};
 
-pub fn extract(prog: &Stmt) -> HashSet<String> {
-    let mut vars = HashSet::new();
-    extract_stmt(prog, &mut vars);
-    vars
+pub struct Vars {
+    // Globals
+    pub scalars: HashSet<String>,
+    pub arrays: HashSet<String>,
+    // For each function whether each of its parameters is an array
+    pub function_arrays: HashMap<String, Vec<bool>>,
 }
 
-fn extract_stmt(stmt: &Stmt, vars: &mut HashSet<String>) {
+// Names used inside the main program or a single function body
+struct Uses {
+    function: Option<String>,
+    params: Vec<String>,
+    scalars: HashSet<String>,
+    arrays: HashSet<String>,
+    // Calls made with the name of each argument that is a bare variable. A bare variable passed to
+    // a function could be a scalar or an array depending on how the callee uses it.
+    calls: Vec<(String, Vec<Option<String>>)>,
+}
+
+impl Uses {
+    fn new(function: Option<String>, params: Vec<String>) -> Self {
+        Uses { function, params, scalars: HashSet::new(), arrays: HashSet::new(), calls: vec![] }
+    }
+    fn param(&self, name: &str) -> Option<usize> {
+        self.params.iter().position(|p| p == name)
+    }
+}
+
+// A name is an array if it is ever subscripted, used with `in`, deleted, or iterated
+// with `for (k in name)`. Every other name is a scalar.
+// Inside a function the parameters are locals and every other name is a global. A name only ever
+// passed straight through to other functions takes its type from the callee's parameter, which is
+// found by iterating until nothing changes.
+// Special variables like NR and ARGV are defined by the runtime so they are left out of the result.
+pub fn extract(main: &Stmt, end: &Stmt, functions: &[Function]) -> Vars {
+    let mut all_uses = vec![];
+    let mut uses = Uses::new(None, vec![]);
+    extract_stmt(main, &mut uses);
+    extract_stmt(end, &mut uses);
+    all_uses.push(uses);
+    for function in functions {
+        if let Some(special) = function.args.iter().find(|arg| is_special(arg)) {
+            panic!("Can't use special variable {} as a parameter of function {}", special, function.name);
+        }
+        let mut uses = Uses::new(Some(function.name.clone()), function.args.clone());
+        extract_stmt(&function.body, &mut uses);
+        all_uses.push(uses);
+    }
+
+    let mut function_arrays: HashMap<String, Vec<bool>> = HashMap::new();
+    let mut arrays: HashSet<String> = SPECIAL_ARRAYS.iter().map(|name| name.to_string()).collect();
+    for uses in all_uses.iter() {
+        for name in uses.arrays.iter() {
+            if uses.param(name).is_none() {
+                arrays.insert(name.clone());
+            }
+        }
+        if let Some(function) = &uses.function {
+            function_arrays.insert(function.clone(), uses.params.iter().map(|p| uses.arrays.contains(p)).collect());
+        }
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for uses in all_uses.iter() {
+            for (callee, args) in uses.calls.iter() {
+                let callee_arrays = match function_arrays.get(callee) {
+                    Some(callee_arrays) => callee_arrays.clone(),
+                    None => panic!("Function {} is not defined", callee),
+                };
+                for (idx, arg) in args.iter().enumerate() {
+                    let name = match arg {
+                        Some(name) if idx < callee_arrays.len() => name,
+                        _ => continue,
+                    };
+                    let param = uses.param(name);
+                    let arg_is_array = match (&uses.function, param) {
+                        (Some(function), Some(param)) => function_arrays[function][param],
+                        _ => arrays.contains(name),
+                    };
+                    if callee_arrays[idx] && !arg_is_array {
+                        match (&uses.function, param) {
+                            (Some(function), Some(param)) => function_arrays.get_mut(function).unwrap()[param] = true,
+                            _ => { arrays.insert(name.clone()); }
+                        }
+                        changed = true;
+                    } else if arg_is_array && !callee_arrays[idx] {
+                        function_arrays.get_mut(callee).unwrap()[idx] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut scalars = HashSet::new();
+    for uses in all_uses.iter() {
+        let passed = uses.calls.iter().flat_map(|(_, args)| args.iter().flatten());
+        for name in uses.scalars.iter().chain(passed) {
+            match (&uses.function, uses.param(name)) {
+                (Some(function), Some(param)) => {
+                    if function_arrays[function][param] && uses.scalars.contains(name) {
+                        panic!("Can't use {} as both a scalar and an array in function {}", name, function);
+                    }
+                }
+                _ => if !arrays.contains(name) || uses.scalars.contains(name) {
+                    scalars.insert(name.clone());
+                }
+            }
+        }
+    }
+    if let Some(both) = scalars.intersection(&arrays).next() {
+        panic!("Can't use {} as both a scalar and an array", both);
+    }
+    if let Some(special) = SPECIAL_SCALARS.iter().find(|name| arrays.contains(**name)) {
+        panic!("Can't use {} as an array", special);
+    }
+    scalars.retain(|name| !is_special(name));
+    arrays.retain(|name| !is_special(name));
+    Vars { scalars, arrays, function_arrays }
+}
+
+fn extract_stmt(stmt: &Stmt, uses: &mut Uses) {
     match stmt {
-        Stmt::Expr(expr) => extract_expr(expr, vars),
-        Stmt::Print(expr) => extract_expr(expr, vars),
-        Stmt::Assign(var, val) => {
-            extract_expr(val, vars);
-            vars.insert(var.clone());
+        Stmt::Expr(expr) => extract_expr(expr, uses),
+        Stmt::Print(expr, redirect) => {
+            extract_expr(expr, uses);
+            extract_redirect(redirect, uses);
+        }
+        Stmt::Printf(format, args, redirect) => {
+            extract_expr(format, uses);
+            extract_exprs(args, uses);
+            extract_redirect(redirect, uses);
         }
-        Stmt::Return(expr) => if let Some(expr) = expr { extract_expr(expr, vars); },
+        Stmt::Return(expr) | Stmt::Exit(expr) => if let Some(expr) = expr { extract_expr(expr, uses); },
+        Stmt::Break | Stmt::Continue | Stmt::Next | Stmt::NextFile => {}
         Stmt::Group(group) => {
             for elem in group {
-                extract_stmt(elem, vars);
+                extract_stmt(elem, uses);
             }
         }
         Stmt::If(test, if_block, else_block) => {
-            extract_expr(test, vars);
-            extract_stmt(if_block, vars);
+            extract_expr(test, uses);
+            extract_stmt(if_block, uses);
             if let Some(else_block) = else_block {
-                extract_stmt(else_block, vars);
+                extract_stmt(else_block, uses);
             }
         }
         Stmt::While(test, body) => {
-            extract_expr(test, vars);
-            extract_stmt(body, vars);
+            extract_expr(test, uses);
+            extract_stmt(body, uses);
+        }
+        Stmt::DoWhile(body, test) => {
+            extract_stmt(body, uses);
+            extract_expr(test, uses);
         }
+        Stmt::For(init, test, incr, body) => {
+            for expr in [init, test, incr].into_iter().flatten() {
+                extract_expr(expr, uses);
+            }
+            extract_stmt(body, uses);
+        }
+        Stmt::ForIn(var, array, body) => {
+            uses.scalars.insert(var.clone());
+            uses.arrays.insert(array.clone());
+            extract_stmt(body, uses);
+        }
+        Stmt::Delete(array, indices) => {
+            if let Some(indices) = indices {
+                extract_exprs(indices, uses);
+            }
+            uses.arrays.insert(array.clone());
+        }
+    }
+}
+
+fn extract_lvalue(lvalue: &LValue, uses: &mut Uses) {
+    match lvalue {
+        LValue::Variable(var) => { uses.scalars.insert(var.clone()); }
+        LValue::Column(col) => extract_expr(col, uses),
+        LValue::ArrayIndex(array, indices) => {
+            extract_exprs(indices, uses);
+            uses.arrays.insert(array.clone());
+        }
+    }
+}
+
+fn extract_redirect(redirect: &Option<Redirect>, uses: &mut Uses) {
+    match redirect {
+        Some(Redirect::Truncate(target)) | Some(Redirect::Append(target)) | Some(Redirect::Pipe(target)) => extract_expr(target, uses),
+        None => {}
+    }
+}
+
+fn extract_exprs(exprs: &[Expr], uses: &mut Uses) {
+    for expr in exprs {
+        extract_expr(expr, uses);
     }
 }
 
-fn extract_expr(expr: &Expr, vars: &mut HashSet<String>) {
+fn extract_expr(expr: &Expr, uses: &mut Uses) {
     match expr {
-        Expr::Variable(var) => {vars.insert(var.clone());},
+        Expr::Variable(var) => {uses.scalars.insert(var.clone());},
         Expr::String(_str) => {},
-        Expr::NumberF64(n) => {}
-        Expr::BinOp(left, op, right) => {
-            extract_expr(left, vars);
-            extract_expr(right, vars);
+        Expr::Regex(_str) => {},
+        Expr::NumberF64(_n) => {}
+        Expr::BinOp(left, _op, right) => {
+            extract_expr(left, uses);
+            extract_expr(right, uses);
+        }
+        Expr::Concat(parts) => extract_exprs(parts, uses),
+        Expr::LogicalOp(left, _op, right) => {
+            extract_expr(left, uses);
+            extract_expr(right, uses);
+        }
+        Expr::Assign(lvalue, val) | Expr::AssignOp(lvalue, _, val) => {
+            extract_lvalue(lvalue, uses);
+            extract_expr(val, uses);
         }
-        Expr::LogicalOp(left, op, right) => {
-            extract_expr(left, vars);
-            extract_expr(right, vars);
+        Expr::Ternary(test, if_true, if_false) => {
+            extract_expr(test, uses);
+            extract_expr(if_true, uses);
+            extract_expr(if_false, uses);
+        }
+        Expr::Not(expr) | Expr::UnaryMinus(expr) | Expr::UnaryPlus(expr) => extract_expr(expr, uses),
+        Expr::PreIncrement(lvalue, _delta) | Expr::PostIncrement(lvalue, _delta) => extract_lvalue(lvalue, uses),
+        Expr::Column(col) => extract_expr(col, uses),
+        Expr::ArrayIndex(array, indices) | Expr::InArray(indices, array) => {
+            extract_exprs(indices, uses);
+            uses.arrays.insert(array.clone());
+        }
+        Expr::Call(name, args) => {
+            let mut names = vec![];
+            for arg in args {
+                match arg {
+                    Expr::Variable(var) => names.push(Some(var.clone())),
+                    _ => {
+                        extract_expr(arg, uses);
+                        names.push(None);
+                    }
+                }
+            }
+            uses.calls.push((name.clone(), names));
+        }
+        Expr::CallBuiltin(Builtin::Split, args) => {
+            extract_expr(&args[0], uses);
+            if let Expr::Variable(name) = &args[1] {
+                uses.arrays.insert(name.clone());
+            }
+            extract_exprs(&args[2..], uses);
+        }
+        Expr::CallBuiltin(_, args) => extract_exprs(args, uses),
+        Expr::NextLine => {}
+        Expr::Getline(source, lvalue) => {
+            match source {
+                GetlineSource::Main => {}
+                GetlineSource::File(expr) | GetlineSource::Command(expr) => extract_expr(expr, uses),
+            }
+            if let Some(lvalue) = lvalue {
+                extract_lvalue(lvalue, uses);
+            }
         }
-        Expr::Column(col) => extract_expr(col, vars),
-        Expr::Call => {}
     }
-}
\ No newline at end of file
+}
@@ -8,6 +8,19 @@ pub fn lex(str: &str) -> LexerResult {
     Ok(lexer.tokens)
 }
 
+// Processes escape sequences the way a string literal does. For strings given on the command
+// line, eg. -F '\t'
+pub fn unescape(str: &str) -> String {
+    let quoted = format!("{}\"", str.replace('"', "\\\""));
+    let mut lexer = Lexer::new(&quoted);
+    if lexer.string().is_ok() {
+        if let Some(Token::String(unescaped)) = lexer.tokens.pop() {
+            return unescaped;
+        }
+    }
+    str.to_string()
+}
+
 
 struct Lexer<'a> {
     src: &'a str,
@@ -21,7 +34,7 @@ type LexerResult = Result<Vec<Token>, (String, usize)>;
 
 
 impl<'a> Lexer<'a> {
-    fn new(src: &'a str) -> Lexer {
+    fn new(src: &'a str) -> Lexer<'a> {
         Lexer {
             src,
             start: 0,
@@ -42,40 +55,140 @@ impl<'a> Lexer<'a> {
         self.tokens.push(tt);
     }
     fn string(&mut self) -> Result<(), String> {
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' { self.line += 1; }
-            self.advance();
-        }
-        if self.is_at_end() {
-            let partial_str = self.src.chars().skip(self.start).take(self.src.len() - self.start).collect::<String>();
-            return Err(format!("Unterminated String: {}", partial_str));
+        let mut str = String::new();
+        loop {
+            if self.is_at_end() {
+                return Err(format!("Unterminated String: \"{}", str));
+            }
+            let c = self.advance();
+            match c {
+                '"' => break,
+                '\\' => {
+                    if self.is_at_end() {
+                        return Err(format!("Unterminated String: \"{}", str));
+                    }
+                    match self.advance() {
+                        '"' => str.push('"'),
+                        '\\' => str.push('\\'),
+                        '/' => str.push('/'),
+                        'a' => str.push('\x07'),
+                        'b' => str.push('\x08'),
+                        'f' => str.push('\x0c'),
+                        'n' => str.push('\n'),
+                        'r' => str.push('\r'),
+                        't' => str.push('\t'),
+                        'v' => str.push('\x0b'),
+                        '\n' => { self.line += 1; } // Line continuation
+                        digit @ '0'..='7' => {
+                            // Up to 3 octal digits eg. "\101" is "A"
+                            let mut code = digit.to_digit(8).unwrap();
+                            for _ in 0..2 {
+                                match self.peek().to_digit(8) {
+                                    Some(next) => {
+                                        code = code * 8 + next;
+                                        self.advance();
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            str.push(char::from_u32(code).unwrap());
+                        }
+                        // Left for the regex engine when the string is used as a dynamic regex
+                        other => {
+                            str.push('\\');
+                            str.push(other);
+                        }
+                    }
+                }
+                '\n' => {
+                    self.line += 1;
+                    str.push(c);
+                }
+                _ => str.push(c),
+            }
         }
-        self.advance();
-        let str = self.src.chars().skip(self.start + 1).take(self.current - self.start - 2).collect::<String>();
         self.add_token(Token::String(str));
-        return Ok(());
+        Ok(())
+    }
+    // A '/' following something that ends an operand is division, anything else starts a regex.
+    // eg. `a / 2` vs `/abc/ { print }`
+    fn slash_is_division(&self) -> bool {
+        matches!(self.tokens.last(),
+            Some(Token::NumberF64(_)) | Some(Token::String(_)) | Some(Token::Regex(_)) |
+            Some(Token::Ident(_)) | Some(Token::RightParen) | Some(Token::RightBracket) | Some(Token::True) | Some(Token::False) |
+            Some(Token::Increment) | Some(Token::Decrement))
+    }
+    // A newline ends a statement only when it follows something that can end one. Newlines after
+    // `{`, `,`, `&&`, `||`, `else`, `do` or an operator continue the statement.
+    fn newline_terminates(&self) -> bool {
+        matches!(self.tokens.last(),
+            Some(Token::NumberF64(_)) | Some(Token::String(_)) | Some(Token::Regex(_)) |
+            Some(Token::Ident(_)) | Some(Token::RightParen) | Some(Token::RightBracket) | Some(Token::True) | Some(Token::False) |
+            Some(Token::Print) | Some(Token::Ret) | Some(Token::Increment) | Some(Token::Decrement) |
+            Some(Token::Break) | Some(Token::Continue) | Some(Token::Next) | Some(Token::NextFile) | Some(Token::Exit) |
+            Some(Token::Getline))
+    }
+    fn regex(&mut self) -> Result<(), String> {
+        let mut regex = String::new();
+        loop {
+            if self.is_at_end() || self.peek() == '\n' {
+                return Err(format!("Unterminated regex: /{}", regex));
+            }
+            let c = self.advance();
+            match c {
+                '/' => break,
+                '\\' => {
+                    if self.is_at_end() {
+                        return Err(format!("Unterminated regex: /{}", regex));
+                    }
+                    // Only escapes meaningful to awk are handled here, the rest are left for the
+                    // regex engine. eg. `\.` stays as `\.`
+                    match self.advance() {
+                        '/' => regex.push('/'),
+                        '"' => regex.push('"'),
+                        'n' => regex.push('\n'),
+                        't' => regex.push('\t'),
+                        'r' => regex.push('\r'),
+                        other => {
+                            regex.push('\\');
+                            regex.push(other);
+                        }
+                    }
+                }
+                _ => regex.push(c),
+            }
+        }
+        self.add_token(Token::Regex(regex));
+        Ok(())
     }
     fn number(&mut self) -> Result<Token, String> {
-        let mut is_float = false;
-        while self.peek().is_digit(10) { self.advance(); }
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
-            is_float = true;
+        while self.peek().is_ascii_digit() { self.advance(); }
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
         }
-        while self.peek().is_digit(10) { self.advance(); }
+        while self.peek().is_ascii_digit() { self.advance(); }
+        // An exponent is only part of the number when digits follow it, `1e` is 1 then the name e
+        if self.peek() == 'e' || self.peek() == 'E' {
+            let sign = self.peek_next() == '+' || self.peek_next() == '-';
+            let first_digit = if sign { self.src.chars().nth(self.current + 2).unwrap_or('\0') } else { self.peek_next() };
+            if first_digit.is_ascii_digit() {
+                self.advance();
+                if sign { self.advance(); }
+                while self.peek().is_ascii_digit() { self.advance(); }
+            }
+        }
 
         let num = self.src.chars().skip(self.start).take(self.current - self.start).collect::<String>();
-        // TODO: scientific notation
         match num.parse::<f64>() {
             Ok(float) => Ok(Token::NumberF64(float)),
             Err(_) => {
-                return Err(format!("Unable to parse f64 {}", num));
+                Err(format!("Unable to parse f64 {}", num))
             }
         }
     }
     #[allow(dead_code)]
     fn number_usize(&mut self, skip: usize) -> Result<usize, String> {
-        while self.peek().is_digit(10) { self.advance(); }
+        while self.peek().is_ascii_digit() { self.advance(); }
         let num = self.src.chars().skip(self.start + skip).take(self.current - (self.start + skip)).collect::<String>();
         let usize = match num.parse::<usize>() {
             Ok(float) => float,
@@ -89,9 +202,9 @@ impl<'a> Lexer<'a> {
         Ok(usize)
     }
     fn identifier(&mut self) -> Result<(), String> {
-        while self.peek().is_alphanumeric() { self.advance(); }
+        while self.peek().is_alphanumeric() || self.peek() == '_' { self.advance(); }
         let src: String = self.src.chars().skip(self.start).take(self.current - self.start).collect();
-        let src = src.to_ascii_lowercase();
+        // Keywords are case sensitive, only BEGIN and END also have a lowercase spelling
         if src == "true" {
             self.add_token(Token::True);
         } else if src == "false" {
@@ -102,7 +215,7 @@ impl<'a> Lexer<'a> {
             self.add_token(Token::If);
         } else if src == "else" {
             self.add_token(Token::Else);
-        } else if src == "begin" {
+        } else if src == "BEGIN" || src == "begin" {
             self.add_token(Token::Begin);
         } else if src == "for" {
             self.add_token(Token::For);
@@ -110,10 +223,33 @@ impl<'a> Lexer<'a> {
             self.add_token(Token::While);
         } else if src == "do" {
             self.add_token(Token::Do);
-        } else if src == "end" {
+        } else if src == "END" || src == "end" {
             self.add_token(Token::End);
         } else if src == "print" {
             self.add_token(Token::Print);
+        } else if src == "printf" {
+            self.add_token(Token::Printf);
+        } else if src == "in" {
+            self.add_token(Token::In);
+        } else if src == "delete" {
+            self.add_token(Token::Delete);
+        } else if src == "function" || src == "func" {
+            self.add_token(Token::Function);
+        } else if src == "break" {
+            self.add_token(Token::Break);
+        } else if src == "continue" {
+            self.add_token(Token::Continue);
+        } else if src == "next" {
+            self.add_token(Token::Next);
+        } else if src == "nextfile" {
+            self.add_token(Token::NextFile);
+        } else if src == "exit" {
+            self.add_token(Token::Exit);
+        } else if src == "getline" {
+            self.add_token(Token::Getline);
+        } else if self.peek() == '(' {
+            // Calls must not have a space between the name and '(' so `a (b)` can be concatenation
+            self.add_token(Token::Call(src));
         } else {
             self.add_token(Token::Ident(src));
         }
@@ -135,21 +271,57 @@ impl<'a> Lexer<'a> {
         let c = self.advance();
         match c {
             '$' => self.add_token(Token::Column),
-            '-' => self.add_token(Token::BinOp(BinOp::Minus)),
-            '+' => self.add_token(Token::BinOp(BinOp::Plus)),
+            '-' => {
+                let tt = if self.matches('-') {
+                    Token::Decrement
+                } else {
+                    self.binop_or_assign(BinOp::Minus)
+                };
+                self.add_token(tt)
+            }
+            '+' => {
+                let tt = if self.matches('+') {
+                    Token::Increment
+                } else {
+                    self.binop_or_assign(BinOp::Plus)
+                };
+                self.add_token(tt)
+            }
             // ';' => self.add_token(Token::Semicolon),
-            '*' => self.add_token(Token::BinOp(BinOp::Star)),
+            '*' => {
+                // ** is the same as ^
+                let tt = if self.matches('*') {
+                    self.binop_or_assign(BinOp::Pow)
+                } else {
+                    self.binop_or_assign(BinOp::Star)
+                };
+                self.add_token(tt)
+            }
+            '%' => {
+                let tt = self.binop_or_assign(BinOp::Modulo);
+                self.add_token(tt)
+            }
+            '^' => {
+                let tt = self.binop_or_assign(BinOp::Pow);
+                self.add_token(tt)
+            }
             '!' => {
-                let tt = match self.matches('=') {
-                    true => Token::BinOp(BinOp::BangEq),
-                    false => Token::Bang,
+                let tt = if self.matches('=') {
+                    Token::BinOp(BinOp::BangEq)
+                } else if self.matches('~') {
+                    Token::BinOp(BinOp::NotMatches)
+                } else {
+                    Token::Bang
                 };
                 self.add_token(tt);
             }
+            '~' => self.add_token(Token::BinOp(BinOp::Matches)),
+            '?' => self.add_token(Token::Question),
+            ':' => self.add_token(Token::Colon),
             '|' => {
                 let tt = match self.matches('|') {
                     true => Token::LogicalOp(LogicalOp::Or),
-                    false => return Err("| must be followed by ||".to_string()),
+                    false => Token::Pipe,
                 };
                 self.add_token(tt);
             }
@@ -175,36 +347,56 @@ impl<'a> Lexer<'a> {
                 self.add_token(tt)
             }
             '>' => {
-                let tt = match self.matches('=') {
-                    true => Token::BinOp(BinOp::GreaterEq),
-                    false => Token::BinOp(BinOp::Greater)
+                let tt = if self.matches('=') {
+                    Token::BinOp(BinOp::GreaterEq)
+                } else if self.matches('>') {
+                    Token::Append
+                } else {
+                    Token::BinOp(BinOp::Greater)
                 };
                 self.add_token(tt)
             }
             '/' => {
-                if self.matches('/') {
-                    while self.peek() != '\n' && !self.is_at_end() {
-                        self.advance();
-                    }
+                if self.slash_is_division() {
+                    let tt = self.binop_or_assign(BinOp::Slash);
+                    self.add_token(tt);
                 } else {
-                    self.add_token(Token::BinOp(BinOp::Slash));
+                    self.regex()?;
+                }
+            }
+            '#' => {
+                while self.peek() != '\n' && !self.is_at_end() {
+                    self.advance();
                 }
             }
             '{' => self.add_token(Token::LeftBrace),
             '}' => self.add_token(Token::RightBrace),
             '(' => self.add_token(Token::LeftParen),
             ')' => self.add_token(Token::RightParen),
+            '[' => self.add_token(Token::LeftBracket),
+            ']' => self.add_token(Token::RightBracket),
             ';' => self.add_token(Token::Semicolon),
+            ',' => self.add_token(Token::Comma),
             '"' => self.string()?,
             '\r' => (),
             '\t' => (),
             ' ' => (),
-            '\n' => self.line += 1,
+            '\n' => {
+                self.line += 1;
+                if self.newline_terminates() {
+                    self.add_token(Token::Newline);
+                }
+            }
+            // Backslash newline continues a line
+            '\\' if self.peek() == '\n' => {
+                self.advance();
+                self.line += 1;
+            }
             _ => {
-                if c.is_digit(10) || (c == '-' && self.peek_next().is_digit(10)) {
+                if c.is_ascii_digit() {
                     let num = self.number()?;
                     self.add_token(num);
-                } else if c.is_alphabetic() {
+                } else if c.is_alphabetic() || c == '_' {
                     self.identifier()?;
                 } else {
                     return Err(format!("Unexpected token::: `{}`", c));
@@ -214,6 +406,15 @@ impl<'a> Lexer<'a> {
         Ok(())
     }
 
+    // An arithmetic operator or its compound assignment eg. `+` or `+=`
+    fn binop_or_assign(&mut self, op: BinOp) -> Token {
+        if self.matches('=') {
+            Token::AssignOp(op)
+        } else {
+            Token::BinOp(op)
+        }
+    }
+
     fn matches(&mut self, expected: char) -> bool {
         if self.is_at_end() { return false; }
         if self.src.chars().nth(self.current).unwrap() != expected { return false; }
@@ -294,7 +495,7 @@ fn test_lex_assignment() {
 #[test]
 fn test_ret() {
     let str = "return 1 return abc";
-    assert_eq!(lex(str).unwrap(), vec![Token::Ret, Token::NumberF64(1.0), Token::Ret, Token::Ident(format!("abc")), Token::EOF]);
+    assert_eq!(lex(str).unwrap(), vec![Token::Ret, Token::NumberF64(1.0), Token::Ret, Token::Ident("abc".to_string()), Token::EOF]);
 }
 
 #[test]
@@ -319,6 +520,19 @@ fn test_ident() {
     assert_eq!(lex(str).unwrap(), vec![Token::LeftBrace, Token::Ident("x".to_string()), Token::RightBrace, Token::EOF]);
 }
 
+#[test]
+fn test_ident_keeps_case() {
+    let str = "NR nr Foo(";
+    assert_eq!(lex(str).unwrap(), vec![Token::Ident("NR".to_string()), Token::Ident("nr".to_string()), Token::Call("Foo".to_string()), Token::LeftParen, Token::EOF]);
+}
+
+#[test]
+fn test_keywords_case_sensitive() {
+    let str = "In Next Exit Delete Function If Begin End";
+    let idents = str.split(' ').map(|name| Token::Ident(name.to_string()));
+    assert_eq!(lex(str).unwrap(), idents.chain(std::iter::once(Token::EOF)).collect::<Vec<_>>());
+}
+
 #[test]
 fn test_string() {
     let str = "{ \"x\" }";
@@ -334,7 +548,7 @@ fn test_string_2() {
 #[test]
 fn test_lex_while_l00p() {
     let str = " while ( x ) { }";
-    assert_eq!(lex(str).unwrap(), vec![Token::While, Token::LeftParen, Token::Ident(format!("x")), Token::RightParen, Token::LeftBrace, Token::RightBrace, Token::EOF]);
+    assert_eq!(lex(str).unwrap(), vec![Token::While, Token::LeftParen, Token::Ident("x".to_string()), Token::RightParen, Token::LeftBrace, Token::RightBrace, Token::EOF]);
 }
 
 #[test]
@@ -346,7 +560,7 @@ fn test_lex_do_while_l00p() {
 #[test]
 fn test_lex_for_l00p() {
     let str = "for (a = 0;";
-    let a = Token::Ident(format!("a"));
+    let a = Token::Ident("a".to_string());
     assert_eq!(lex(str).unwrap(), vec![Token::For, Token::LeftParen, a.clone(), Token::Eq, Token::NumberF64(0.0), Token::Semicolon, Token::EOF]);
 }
 
@@ -354,4 +568,139 @@ fn test_lex_for_l00p() {
 fn test_lt_gt_eq() {
     let str = "< <= >= >";
     assert_eq!(lex(str).unwrap(), vec![Token::BinOp(BinOp::Less), Token::BinOp(BinOp::LessEq), Token::BinOp(BinOp::GreaterEq), Token::BinOp(BinOp::Greater), Token::EOF]);
-}
\ No newline at end of file
+}
+#[test]
+fn test_regex() {
+    let str = "/abc/ { print }";
+    assert_eq!(lex(str).unwrap(), vec![Token::Regex("abc".to_string()), Token::LeftBrace, Token::Print, Token::RightBrace, Token::EOF]);
+}
+
+#[test]
+fn test_regex_escaped_slash() {
+    let str = "/a\\/b\\.c/";
+    assert_eq!(lex(str).unwrap(), vec![Token::Regex("a/b\\.c".to_string()), Token::EOF]);
+}
+
+#[test]
+fn test_regex_vs_division() {
+    let str = "a / 2 / b";
+    let a = Token::Ident("a".to_string());
+    let b = Token::Ident("b".to_string());
+    assert_eq!(lex(str).unwrap(), vec![a, Token::BinOp(BinOp::Slash), Token::NumberF64(2.0), Token::BinOp(BinOp::Slash), b, Token::EOF]);
+}
+
+#[test]
+fn test_comment() {
+    let str = "# a comment\n{ x } # another";
+    assert_eq!(lex(str).unwrap(), vec![Token::LeftBrace, Token::Ident("x".to_string()), Token::RightBrace, Token::EOF]);
+}
+
+#[test]
+fn test_match_ops() {
+    let str = "$1 ~ /a/ !~ b";
+    assert_eq!(lex(str).unwrap(), vec![Token::Column, Token::NumberF64(1.0), Token::BinOp(BinOp::Matches), Token::Regex("a".to_string()), Token::BinOp(BinOp::NotMatches), Token::Ident("b".to_string()), Token::EOF]);
+}
+
+#[test]
+fn test_range() {
+    let str = "/a/, /b/";
+    assert_eq!(lex(str).unwrap(), vec![Token::Regex("a".to_string()), Token::Comma, Token::Regex("b".to_string()), Token::EOF]);
+}
+
+#[test]
+fn test_arrays() {
+    let str = "a[1, 2] (k in a) delete a";
+    let a = Token::Ident("a".to_string());
+    assert_eq!(lex(str).unwrap(), vec![a.clone(), Token::LeftBracket, Token::NumberF64(1.0), Token::Comma, Token::NumberF64(2.0), Token::RightBracket,
+                                       Token::LeftParen, Token::Ident("k".to_string()), Token::In, a.clone(), Token::RightParen, Token::Delete, a, Token::EOF]);
+}
+
+#[test]
+fn test_function() {
+    let str = "function my_func(a, b) { return a } my_func (1)";
+    let a = Token::Ident("a".to_string());
+    assert_eq!(lex(str).unwrap(), vec![Token::Function, Token::Call("my_func".to_string()), Token::LeftParen, a.clone(), Token::Comma, Token::Ident("b".to_string()), Token::RightParen,
+                                       Token::LeftBrace, Token::Ret, a, Token::RightBrace, Token::Ident("my_func".to_string()), Token::LeftParen, Token::NumberF64(1.0), Token::RightParen, Token::EOF]);
+}
+
+#[test]
+fn test_string_escapes() {
+    let str = r#""a\tb\n" "q\"q" "\101\\" "\.""#;
+    assert_eq!(lex(str).unwrap(), vec![Token::String("a\tb\n".to_string()), Token::String("q\"q".to_string()), Token::String("A\\".to_string()), Token::String("\\.".to_string()), Token::EOF]);
+}
+
+#[test]
+fn test_unescape() {
+    assert_eq!(unescape(r"\t"), "\t");
+    assert_eq!(unescape(r#"a"b\|"#), "a\"b\\|");
+    assert_eq!(unescape(","), ",");
+}
+
+#[test]
+fn test_printf() {
+    let str = "printf \"%d\", 1";
+    assert_eq!(lex(str).unwrap(), vec![Token::Printf, Token::String("%d".to_string()), Token::Comma, Token::NumberF64(1.0), Token::EOF]);
+}
+
+#[test]
+fn test_newline_terminates() {
+    let str = "{ x = 1\n\n y = 2 +\n 3 \\\n z }";
+    let x = Token::Ident("x".to_string());
+    let y = Token::Ident("y".to_string());
+    assert_eq!(lex(str).unwrap(), vec![Token::LeftBrace, x, Token::Eq, Token::NumberF64(1.0), Token::Newline, y, Token::Eq, Token::NumberF64(2.0),
+                                       Token::BinOp(BinOp::Plus), Token::NumberF64(3.0), Token::Ident("z".to_string()), Token::RightBrace, Token::EOF]);
+}
+
+#[test]
+fn test_minus_not_folded() {
+    let x = Token::Ident("x".to_string());
+    assert_eq!(lex("x-5").unwrap(), vec![x.clone(), Token::BinOp(BinOp::Minus), Token::NumberF64(5.0), Token::EOF]);
+    assert_eq!(lex("-5").unwrap(), vec![Token::BinOp(BinOp::Minus), Token::NumberF64(5.0), Token::EOF]);
+}
+
+#[test]
+fn test_arithmetic_ops() {
+    let x = Token::Ident("x".to_string());
+    assert_eq!(lex("x % 2 ^ 3 ** 4").unwrap(), vec![x.clone(), Token::BinOp(BinOp::Modulo), Token::NumberF64(2.0), Token::BinOp(BinOp::Pow), Token::NumberF64(3.0), Token::BinOp(BinOp::Pow), Token::NumberF64(4.0), Token::EOF]);
+    assert_eq!(lex("x++ --x").unwrap(), vec![x.clone(), Token::Increment, Token::Decrement, x.clone(), Token::EOF]);
+}
+
+#[test]
+fn test_control_flow_keywords() {
+    assert_eq!(lex("break continue next nextfile exit\n").unwrap(), vec![Token::Break, Token::Continue, Token::Next, Token::NextFile, Token::Exit, Token::Newline, Token::EOF]);
+}
+
+#[test]
+fn test_getline() {
+    assert_eq!(lex("\"cmd\" | getline x || getline < f").unwrap(), vec![
+        Token::String("cmd".to_string()), Token::Pipe, Token::Getline, Token::Ident("x".to_string()), Token::LogicalOp(LogicalOp::Or),
+        Token::Getline, Token::BinOp(BinOp::Less), Token::Ident("f".to_string()), Token::EOF]);
+}
+
+#[test]
+fn test_redirects() {
+    let x = Token::Ident("x".to_string());
+    assert_eq!(lex("print x > f >> g | h >= 1").unwrap(), vec![
+        Token::Print, x.clone(), Token::BinOp(BinOp::Greater), Token::Ident("f".to_string()), Token::Append, Token::Ident("g".to_string()),
+        Token::Pipe, Token::Ident("h".to_string()), Token::BinOp(BinOp::GreaterEq), Token::NumberF64(1.0), Token::EOF]);
+}
+
+#[test]
+fn test_ternary() {
+    assert_eq!(lex("a ? /x/ : 1").unwrap(), vec![Token::Ident("a".to_string()), Token::Question, Token::Regex("x".to_string()), Token::Colon, Token::NumberF64(1.0), Token::EOF]);
+}
+
+#[test]
+fn test_assign_ops() {
+    let x = Token::Ident("x".to_string());
+    let ops = vec![("+=", BinOp::Plus), ("-=", BinOp::Minus), ("*=", BinOp::Star), ("/=", BinOp::Slash), ("%=", BinOp::Modulo), ("^=", BinOp::Pow), ("**=", BinOp::Pow)];
+    for (src, op) in ops {
+        assert_eq!(lex(&format!("x {} 1", src)).unwrap(), vec![x.clone(), Token::AssignOp(op), Token::NumberF64(1.0), Token::EOF]);
+    }
+}
+
+#[test]
+fn test_scientific_notation() {
+    assert_eq!(lex("1.5e-3 2E3 1e+2").unwrap(), vec![Token::NumberF64(1.5e-3), Token::NumberF64(2000.0), Token::NumberF64(100.0), Token::EOF]);
+    assert_eq!(lex("1e").unwrap(), vec![Token::NumberF64(1.0), Token::Ident("e".to_string()), Token::EOF]);
+}
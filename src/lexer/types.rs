@@ -7,12 +7,16 @@ pub enum BinOp {
     Plus,
     Slash,
     Star,
+    Modulo,
+    Pow,
     Greater,
     GreaterEq,
     Less,
     LessEq,
     BangEq,
     EqEq,
+    Matches,
+    NotMatches,
 }
 
 impl BinOp {
@@ -36,12 +40,16 @@ impl Display for BinOp {
             BinOp::Plus => f.write_str("+"),
             BinOp::Slash => f.write_str("/"),
             BinOp::Star => f.write_str("*"),
+            BinOp::Modulo => f.write_str("%"),
+            BinOp::Pow => f.write_str("^"),
             BinOp::Greater => f.write_str(">"),
             BinOp::GreaterEq => f.write_str(">="),
             BinOp::Less => f.write_str("<"),
             BinOp::LessEq => f.write_str("<="),
             BinOp::BangEq => f.write_str("!="),
             BinOp::EqEq => f.write_str("=="),
+            BinOp::Matches => f.write_str("~"),
+            BinOp::NotMatches => f.write_str("!~"),
         }
     }
 }
@@ -62,15 +70,27 @@ impl Display for LogicalOp {
 }
 
 #[derive(Debug, Clone, PartialOrd, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     Eq,
     Semicolon,
+    Newline,
+    Comma,
     Column,
     BinOp(BinOp),
     LogicalOp(LogicalOp),
     Bang,
+    Question,
+    Colon,
+    // += -= *= /= %= ^=
+    AssignOp(BinOp),
+    Increment,
+    Decrement,
     String(String),
+    Regex(String),
     Ident(String),
+    // An identifier immediately followed by '(' eg. `foo(`
+    Call(String),
     NumberF64(f64),
     False,
     True,
@@ -79,7 +99,10 @@ pub enum Token {
     RightBrace,
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     Print,
+    Printf,
     Ret,
     If,
     Begin,
@@ -88,23 +111,48 @@ pub enum Token {
     While,
     For,
     Do,
+    In,
+    Delete,
+    Function,
+    Break,
+    Continue,
+    Next,
+    NextFile,
+    Exit,
+    Getline,
+    // | in `cmd | getline` or `print | cmd`
+    Pipe,
+    // >> in `print >> file`
+    Append,
 }
 
 #[derive(Debug, Clone, PartialOrd, PartialEq, Hash, Eq, Ord)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {
     Minus,
     Plus,
     Slash,
     Star,
+    Modulo,
+    Pow,
     Bang,
+    Question,
+    Colon,
+    AssignOp,
+    Increment,
+    Decrement,
     BangEq,
     EqEq,
+    Matches,
+    NotMatches,
     Greater,
     GreaterEq,
     Ident,
+    Call,
     Less,
     LessEq,
     String,
+    Regex,
     NumberF64,
     And,
     Or,
@@ -116,8 +164,13 @@ pub enum TokenType {
     RightBrace,
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     Print,
+    Printf,
     Semicolon,
+    Newline,
+    Comma,
     Eq,
     Ret,
     If,
@@ -127,6 +180,17 @@ pub enum TokenType {
     For,
     While,
     Do,
+    In,
+    Delete,
+    Function,
+    Break,
+    Continue,
+    Next,
+    NextFile,
+    Exit,
+    Getline,
+    Pipe,
+    Append,
 }
 
 impl Token {
@@ -139,12 +203,16 @@ impl Token {
                     BinOp::Plus => TokenType::Plus,
                     BinOp::Slash => TokenType::Slash,
                     BinOp::Star => TokenType::Star,
+                    BinOp::Modulo => TokenType::Modulo,
+                    BinOp::Pow => TokenType::Pow,
                     BinOp::Greater => TokenType::Greater,
                     BinOp::GreaterEq => TokenType::GreaterEq,
                     BinOp::Less => TokenType::Less,
                     BinOp::LessEq => TokenType::LessEq,
                     BinOp::BangEq => TokenType::BangEq,
                     BinOp::EqEq => TokenType::EqEq,
+                    BinOp::Matches => TokenType::Matches,
+                    BinOp::NotMatches => TokenType::NotMatches,
                 }
             }
             Token::LogicalOp(logical_op) => {
@@ -154,7 +222,13 @@ impl Token {
                 }
             }
             Token::Bang => TokenType::Bang,
+            Token::Question => TokenType::Question,
+            Token::Colon => TokenType::Colon,
+            Token::AssignOp(_) => TokenType::AssignOp,
+            Token::Increment => TokenType::Increment,
+            Token::Decrement => TokenType::Decrement,
             Token::String(_) => TokenType::String,
+            Token::Regex(_) => TokenType::Regex,
             Token::NumberF64(_) => TokenType::NumberF64,
             Token::False => TokenType::False,
             Token::True => TokenType::True,
@@ -164,8 +238,13 @@ impl Token {
             Token::RightBrace => TokenType::RightBrace,
             Token::LeftParen => TokenType::LeftParen,
             Token::RightParen => TokenType::RightParen,
+            Token::LeftBracket => TokenType::LeftBracket,
+            Token::RightBracket => TokenType::RightBracket,
             Token::Print => TokenType::Print,
+            Token::Printf => TokenType::Printf,
             Token::Semicolon => TokenType::Semicolon,
+            Token::Newline => TokenType::Newline,
+            Token::Comma => TokenType::Comma,
             Token::Eq => TokenType::Eq,
             Token::Ret => TokenType::Ret,
             Token::If => TokenType::If,
@@ -173,9 +252,21 @@ impl Token {
             Token::End => TokenType::End,
             Token::Begin => TokenType::Begin,
             Token::Ident(_) => TokenType::Ident,
+            Token::Call(_) => TokenType::Call,
             Token::While => TokenType::While,
             Token::For => TokenType::For,
             Token::Do => TokenType::Do,
+            Token::In => TokenType::In,
+            Token::Delete => TokenType::Delete,
+            Token::Function => TokenType::Function,
+            Token::Break => TokenType::Break,
+            Token::Continue => TokenType::Continue,
+            Token::Next => TokenType::Next,
+            Token::NextFile => TokenType::NextFile,
+            Token::Exit => TokenType::Exit,
+            Token::Getline => TokenType::Getline,
+            Token::Pipe => TokenType::Pipe,
+            Token::Append => TokenType::Append,
         }
     }
 }
@@ -189,12 +280,22 @@ impl TokenType {
             TokenType::Slash => "Slash",
             TokenType::Star => "Star",
             TokenType::Bang => "Bang",
+            TokenType::Question => "Question",
+            TokenType::Colon => "Colon",
+            TokenType::AssignOp => "AssignOp",
+            TokenType::Increment => "Increment",
+            TokenType::Decrement => "Decrement",
+            TokenType::Modulo => "Modulo",
+            TokenType::Pow => "Pow",
             TokenType::EqEq => "EqEq",
+            TokenType::Matches => "Matches",
+            TokenType::NotMatches => "NotMatches",
             TokenType::Greater => "Greater",
             TokenType::GreaterEq => "GreaterEq",
             TokenType::Less => "Less",
             TokenType::LessEq => "LessEq",
             TokenType::String => "String",
+            TokenType::Regex => "Regex",
             TokenType::NumberF64 => "NumberF64",
             TokenType::And => "And",
             TokenType::Or => "Or",
@@ -207,8 +308,13 @@ impl TokenType {
             TokenType::RightBrace => "RightBrace",
             TokenType::LeftParen => "LeftParen",
             TokenType::RightParen => "RightParen",
+            TokenType::LeftBracket => "LeftBracket",
+            TokenType::RightBracket => "RightBracket",
             TokenType::Print => "Print",
+            TokenType::Printf => "Printf",
             TokenType::Semicolon => "Semicolon",
+            TokenType::Newline => "Newline",
+            TokenType::Comma => "Comma",
             TokenType::Eq => "Eq",
             TokenType::Ret => "Ret",
             TokenType::If => "If",
@@ -216,8 +322,20 @@ impl TokenType {
             TokenType::Begin => "Begin",
             TokenType::End => "End",
             TokenType::Ident => "Ident",
+            TokenType::Call => "Call",
             TokenType::For => "For",
             TokenType::Do => "Do",
+            TokenType::In => "In",
+            TokenType::Delete => "Delete",
+            TokenType::Function => "Function",
+            TokenType::Break => "Break",
+            TokenType::Continue => "Continue",
+            TokenType::Next => "Next",
+            TokenType::NextFile => "NextFile",
+            TokenType::Exit => "Exit",
+            TokenType::Getline => "Getline",
+            TokenType::Pipe => "Pipe",
+            TokenType::Append => "Append",
         }
     }
 }
\ No newline at end of file
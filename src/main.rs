@@ -1,5 +1,5 @@
 use crate::args::AwkArgs;
-use crate::lexer::{BinOp, lex};
+use crate::lexer::{BinOp, lex, unescape};
 use crate::parser::{Expr, parse};
 use crate::runner::run;
 use crate::transformer::transform;
@@ -28,7 +28,8 @@ fn main() {
         }
     };
     let ast = transform(parse(lex(&program).unwrap()));
-    let bitcode = codgen::compile(ast, args.files.as_slice(), args.dump);
+    let field_separator = args.field_separator.as_deref().map(unescape);
+    let bitcode = codgen::compile(ast, args.files.as_slice(), field_separator.as_deref(), args.non_decimal_data, args.dump);
     run(bitcode, args.save_executable);
 }
 
@@ -1,24 +1,27 @@
 mod types;
 
 pub use types::{Stmt, Expr, Program};
-pub use crate::parser::types::{PatternAction};
-use crate::lexer::{BinOp, Token, TokenType};
+pub use crate::parser::types::{PatternAction, Pattern, LValue, Function, Builtin, GetlineSource, Redirect};
+use crate::lexer::{BinOp, LogicalOp, Token, TokenType};
 
 
 enum PAType {
     Normal(PatternAction),
     Begin(Stmt),
     End(Stmt),
+    Function(Function),
 }
 
 pub fn parse(tokens: Vec<Token>) -> Program {
-    let mut parser = Parser { tokens, current: 0 };
+    let mut parser = Parser { tokens, current: 0, in_print: false };
     parser.parse()
 }
 
 struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    // Set while parsing the unparenthesized expression of a print so `>` starts a redirect
+    in_print: bool,
 }
 
 impl Parser {
@@ -26,14 +29,19 @@ impl Parser {
         let mut begin = vec![];
         let mut end = vec![];
         let mut generic = vec![];
+        let mut functions = vec![];
         while !self.is_at_end() {
+            if self.matches(vec![TokenType::Newline, TokenType::Semicolon]) {
+                continue;
+            }
             match self.pattern_action() {
                 PAType::Normal(pa) => generic.push(pa),
                 PAType::Begin(pa) => begin.push(pa),
                 PAType::End(pa) => end.push(pa),
+                PAType::Function(func) => functions.push(func),
             }
         }
-        Program::new(begin, end, generic)
+        Program::new_with_functions(begin, end, generic, functions)
     }
 
     fn check(&mut self, typ: TokenType) -> bool {
@@ -75,11 +83,11 @@ impl Parser {
     }
 
     fn peek(&self) -> Token {
-        return self.tokens[self.current].clone();
+        self.tokens[self.current].clone()
     }
 
     fn peek_next(&self) -> Token {
-        return self.tokens[self.current + 1].clone();
+        self.tokens[self.current + 1].clone()
     }
 
     fn is_at_end(&self) -> bool {
@@ -94,7 +102,7 @@ impl Parser {
     }
 
     fn pattern_action(&mut self) -> PAType {
-        let b = if self.matches(vec![TokenType::LeftBrace]) {
+        if self.matches(vec![TokenType::LeftBrace]) {
             // { print 1; }
             let pa = PAType::Normal(PatternAction::new_action_only(self.stmts()));
             self.consume(TokenType::RightBrace, "Expected '}' after action block");
@@ -111,8 +119,22 @@ impl Parser {
             let pa = PAType::End(self.stmts());
             self.consume(TokenType::RightBrace, "End action should end with '}'");
             pa
+        } else if self.matches(vec![TokenType::Function]) {
+            // function name(a, b) { return a + b; }
+            PAType::Function(self.function())
         } else {
             let test = self.expression();
+            if self.matches(vec![TokenType::Comma]) {
+                // start, end { print 1; }
+                let end = self.expression();
+                return if self.matches(vec![TokenType::LeftBrace]) {
+                    let pa = PAType::Normal(PatternAction::new_range(test, end, self.stmts()));
+                    self.consume(TokenType::RightBrace, "Range patern action should end with '}'");
+                    pa
+                } else {
+                    PAType::Normal(PatternAction::new_range_only(test, end))
+                };
+            }
             if self.matches(vec![TokenType::LeftBrace]) {
                 // test { print 1; }
                 let pa = PAType::Normal(PatternAction::new(Some(test), self.stmts()));
@@ -123,50 +145,80 @@ impl Parser {
                 // ^ implicitly prints line if test passes
                 PAType::Normal(PatternAction::new_pattern_only(test))
             }
+        }
+    }
+    fn function(&mut self) -> Function {
+        let name = match self.advance() {
+            Token::Ident(name) | Token::Call(name) => name,
+            t => panic!("Expected a function name after 'function' found {:?}", t),
         };
-        b
+        if Builtin::from_name(&name).is_some() {
+            panic!("Cannot define a function named {}, it is a built-in", name);
+        }
+        self.consume(TokenType::LeftParen, "Expected '(' after function name");
+        let mut args = vec![];
+        while !self.check(TokenType::RightParen) {
+            args.push(self.ident("Expected a parameter name"));
+            if !self.matches(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightParen, "Expected ')' after function parameters");
+        self.skip_newlines();
+        self.consume(TokenType::LeftBrace, "Expected '{' to start function body");
+        let body = if self.check(TokenType::RightBrace) { Stmt::Group(vec![]) } else { self.stmts() };
+        self.consume(TokenType::RightBrace, "Expected '}' after function body");
+        Function::new(name, args, body)
     }
-    fn group(&mut self) -> Stmt {
-        self.consume(TokenType::LeftBrace, "Expected a '}'");
-        let s = self.stmts();
-        self.consume(TokenType::RightBrace, "Expected a '}'");
-        s
+
+    fn lvalue(expr: Expr) -> LValue {
+        match expr {
+            Expr::Variable(name) => LValue::Variable(name),
+            Expr::Column(col) => LValue::Column(*col),
+            Expr::ArrayIndex(name, indices) => LValue::ArrayIndex(name, indices),
+            other => panic!("Cannot assign to {}", other),
+        }
+    }
+
+    fn is_lvalue(expr: &Expr) -> bool {
+        matches!(expr, Expr::Variable(_) | Expr::Column(_) | Expr::ArrayIndex(_, _))
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.matches(vec![TokenType::Newline]) {}
+    }
+
+    // A statement ends at a ';', a newline, or the '}' closing its block
+    fn at_terminator(&mut self) -> bool {
+        self.check(TokenType::Semicolon) || self.check(TokenType::Newline) || self.check(TokenType::RightBrace)
+    }
+
+    fn at_redirect(&mut self) -> bool {
+        self.check(TokenType::Greater) || self.check(TokenType::Append) || self.check(TokenType::Pipe)
+    }
+
+    // The optional `> file`, `>> file` or `| cmd` after print or printf. The target is a
+    // concatenation so `print > "out" i` writes to "out" i.
+    fn redirect(&mut self) -> Option<Redirect> {
+        if self.matches(vec![TokenType::Greater]) {
+            Some(Redirect::Truncate(self.concatenation()))
+        } else if self.matches(vec![TokenType::Append]) {
+            Some(Redirect::Append(self.concatenation()))
+        } else if self.matches(vec![TokenType::Pipe]) {
+            Some(Redirect::Pipe(self.concatenation()))
+        } else {
+            None
+        }
     }
 
     fn stmts(&mut self) -> Stmt {
         let mut stmts = vec![];
         while self.peek().ttype() != TokenType::RightBrace {
-            let stmt = if self.matches(vec![TokenType::Print]) {
-                Stmt::Print(self.expression())
-            } else if self.peek_next().ttype() == TokenType::Eq {
-                let str = if let Token::Ident(str) = self.consume(TokenType::Ident, "Expected identifier before '='") { str } else { panic!("Expected identifier before '='") };
-                self.consume(TokenType::Eq, "Expected '=' after identifier");
-                Stmt::Assign(str, self.expression())
-            } else if self.matches(vec![TokenType::Ret]) {
-                self.return_stmt()
-            } else if self.matches(vec![TokenType::While]) {
-                self.consume(TokenType::LeftParen, "Must have paren after while");
-                let expr = self.expression();
-                self.consume(TokenType::RightParen, "Must have right parent after while statement test expression");
-                self.consume(TokenType::LeftBrace, "Must have brace after `while (expr)`");
-                let stmts = self.stmts();
-                self.consume(TokenType::RightBrace, "While loop must be followed by '}'");
-                Stmt::While(expr, Box::new(stmts))
-            } else if self.matches(vec![TokenType::Print]) {
-                let expr = self.expression();
-                Stmt::Print(expr)
-            } else if self.matches(vec![TokenType::If]) {
-                self.if_stmt()
-            } else if self.matches(vec![TokenType::LeftBrace]) {
-                let s = self.stmts();
-                self.consume(TokenType::RightBrace, "Expected a right brace after a group");
-                s
-            } else {
-                Stmt::Expr(self.expression())
-            };
-            if self.peek().ttype() == TokenType::Semicolon {
-                self.consume(TokenType::Semicolon, "not possible");
+            if self.matches(vec![TokenType::Semicolon, TokenType::Newline]) {
+                continue;
             }
+            let stmt = self.stmt();
+            self.matches(vec![TokenType::Semicolon, TokenType::Newline]);
             stmts.push(stmt);
         }
         if stmts.len() == 1 {
@@ -174,8 +226,202 @@ impl Parser {
         }
         Stmt::Group(stmts)
     }
+
+    // A single statement. Does not consume the terminator after a simple statement.
+    fn stmt(&mut self) -> Stmt {
+        if self.matches(vec![TokenType::Print]) {
+            let expr = if self.at_terminator() || self.at_redirect() {
+                // `print` alone prints the whole line
+                Expr::Column(Box::new(Expr::NumberF64(0.0)))
+            } else {
+                self.print_expression()
+            };
+            Stmt::Print(expr, self.redirect())
+        } else if self.matches(vec![TokenType::Printf]) {
+            self.printf_stmt()
+        } else if self.matches(vec![TokenType::Ret]) {
+            self.return_stmt()
+        } else if self.matches(vec![TokenType::Break]) {
+            Stmt::Break
+        } else if self.matches(vec![TokenType::Continue]) {
+            Stmt::Continue
+        } else if self.matches(vec![TokenType::Next]) {
+            Stmt::Next
+        } else if self.matches(vec![TokenType::NextFile]) {
+            Stmt::NextFile
+        } else if self.matches(vec![TokenType::Exit]) {
+            if self.at_terminator() {
+                Stmt::Exit(None)
+            } else {
+                Stmt::Exit(Some(self.expression()))
+            }
+        } else if self.matches(vec![TokenType::While]) {
+            self.consume(TokenType::LeftParen, "Must have paren after while");
+            let expr = self.expression();
+            self.consume(TokenType::RightParen, "Must have right parent after while statement test expression");
+            Stmt::While(expr, Box::new(self.body()))
+        } else if self.matches(vec![TokenType::Do]) {
+            self.do_stmt()
+        } else if self.matches(vec![TokenType::For]) {
+            self.for_stmt()
+        } else if self.matches(vec![TokenType::Delete]) {
+            let name = self.ident("Expected an array name after delete");
+            if self.check(TokenType::LeftBracket) {
+                Stmt::Delete(name, Some(self.subscripts()))
+            } else {
+                Stmt::Delete(name, None)
+            }
+        } else if self.matches(vec![TokenType::If]) {
+            self.if_stmt()
+        } else if self.matches(vec![TokenType::LeftBrace]) {
+            let s = self.stmts();
+            self.consume(TokenType::RightBrace, "Expected a right brace after a group");
+            s
+        } else if self.matches(vec![TokenType::Semicolon]) {
+            // An empty statement eg. `while (x--) ;`
+            Stmt::Group(vec![])
+        } else {
+            Stmt::Expr(self.expression())
+        }
+    }
+
+    // The body of an if, else, while, do or for. Either a { group } or a single statement.
+    fn body(&mut self) -> Stmt {
+        self.skip_newlines();
+        self.stmt()
+    }
+
+    // Consumes `keyword` if it is the next token after any ';' and newlines ending the previous
+    // statement, eg. the else in `if (x) print 1; else print 2`. Otherwise consumes nothing.
+    fn matches_after_terminator(&mut self, keyword: TokenType) -> bool {
+        let start = self.current;
+        while self.matches(vec![TokenType::Semicolon, TokenType::Newline]) {}
+        if self.matches(vec![keyword]) {
+            return true;
+        }
+        self.current = start;
+        false
+    }
+
+    fn do_stmt(&mut self) -> Stmt {
+        let body = self.body();
+        if !self.matches_after_terminator(TokenType::While) {
+            panic!("Expected while after the body of do");
+        }
+        self.consume(TokenType::LeftParen, "Must have paren after do ... while");
+        let test = self.expression();
+        self.consume(TokenType::RightParen, "Must have right paren after do ... while test expression");
+        Stmt::DoWhile(Box::new(body), test)
+    }
+
+    fn for_stmt(&mut self) -> Stmt {
+        self.consume(TokenType::LeftParen, "Expected '(' after for");
+        let is_for_in = self.check(TokenType::Ident)
+            && self.peek_next().ttype() == TokenType::In
+            && self.tokens.get(self.current + 3).map(|t| t.ttype()) == Some(TokenType::RightParen);
+        if is_for_in {
+            let var = self.ident("Expected a variable name in for (var in array)");
+            self.consume(TokenType::In, "Expected 'in' after variable in for (var in array)");
+            let array = self.ident("Expected an array name in for (var in array)");
+            self.consume(TokenType::RightParen, "Expected ')' after for (var in array");
+            return Stmt::ForIn(var, array, Box::new(self.body()));
+        }
+        // for (init; test; incr) body where each of init, test and incr may be left out
+        let init = self.optional_expression(TokenType::Semicolon);
+        self.consume(TokenType::Semicolon, "Expected ';' after for loop initializer");
+        self.skip_newlines();
+        let test = self.optional_expression(TokenType::Semicolon);
+        self.consume(TokenType::Semicolon, "Expected ';' after for loop test");
+        self.skip_newlines();
+        let incr = self.optional_expression(TokenType::RightParen);
+        self.consume(TokenType::RightParen, "Expected ')' after for loop increment");
+        Stmt::For(init, test, incr, Box::new(self.body()))
+    }
+
+    fn optional_expression(&mut self, end: TokenType) -> Option<Expr> {
+        if self.check(end) {
+            None
+        } else {
+            Some(self.expression())
+        }
+    }
+
+    fn ident(&mut self, message: &str) -> String {
+        match self.consume(TokenType::Ident, message) {
+            Token::Ident(name) => name,
+            _ => panic!("{}", message),
+        }
+    }
+
+    // [expr, expr, ...]
+    fn subscripts(&mut self) -> Vec<Expr> {
+        self.consume(TokenType::LeftBracket, "Expected '[' to start array subscript");
+        let mut indices = vec![self.expression()];
+        while self.matches(vec![TokenType::Comma]) {
+            indices.push(self.expression());
+        }
+        self.consume(TokenType::RightBracket, "Expected ']' after array subscript");
+        indices
+    }
+
+    // printf fmt, a, b or printf(fmt, a, b)
+    fn printf_stmt(&mut self) -> Stmt {
+        let mut args = if self.matches(vec![TokenType::LeftParen]) {
+            let args = self.expression_list();
+            self.consume(TokenType::RightParen, "Expected ')' after printf arguments");
+            args
+        } else {
+            let mut args = vec![self.print_expression()];
+            while self.matches(vec![TokenType::Comma]) {
+                args.push(self.print_expression());
+            }
+            args
+        };
+        if args.is_empty() {
+            panic!("printf requires a format string");
+        }
+        let format = args.remove(0);
+        Stmt::Printf(format, args, self.redirect())
+    }
+
+    fn expression_list(&mut self) -> Vec<Expr> {
+        let mut exprs = vec![self.expression()];
+        while self.matches(vec![TokenType::Comma]) {
+            exprs.push(self.expression());
+        }
+        exprs
+    }
+
+    // Checks the arguments of a builtin call
+    fn builtin_call(builtin: Builtin, args: Vec<Expr>) -> Expr {
+        let (min, max) = builtin.arity();
+        if args.len() < min || args.len() > max {
+            panic!("Wrong number of arguments to {}", builtin.name());
+        }
+        match builtin {
+            Builtin::Split if !matches!(args[1], Expr::Variable(_)) => panic!("The second argument to split must be an array name"),
+            Builtin::Sub | Builtin::Gsub if args.len() == 3 && !Parser::is_lvalue(&args[2]) =>
+                panic!("The target of {} must be a variable, field or array element", builtin.name()),
+            _ => {}
+        }
+        Expr::CallBuiltin(builtin, args)
+    }
+
+    // Arguments to a call after the '(', consumes the ')'
+    fn call_args(&mut self) -> Vec<Expr> {
+        let mut args = vec![];
+        while !self.check(TokenType::RightParen) {
+            args.push(self.expression());
+            if !self.matches(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightParen, "Expected ')' after function arguments");
+        args
+    }
+
     fn return_stmt(&mut self) -> Stmt {
-        if self.peek().ttype() == TokenType::Semicolon {
+        if self.at_terminator() {
             Stmt::Return(None)
         } else {
             Stmt::Return(Some(self.expression()))
@@ -185,9 +431,9 @@ impl Parser {
         self.consume(TokenType::LeftParen, "Expected '(' after if");
         let predicate = self.expression();
         self.consume(TokenType::RightParen, "Expected ')' after if predicate");
-        let then_blk = self.group();
-        let else_blk = if self.matches(vec![TokenType::Else]) {
-            Some(Box::new(self.group()))
+        let then_blk = self.body();
+        let else_blk = if self.matches_after_terminator(TokenType::Else) {
+            Some(Box::new(self.body()))
         } else {
             None
         };
@@ -195,15 +441,97 @@ impl Parser {
     }
 
     fn expression(&mut self) -> Expr {
-        if self.matches(vec![TokenType::Column]) {
-            return Expr::Column(Box::new(self.compare()));
+        // Inside parens, brackets or call arguments `>` is a comparison again
+        let in_print = self.in_print;
+        self.in_print = false;
+        let expr = self.assignment();
+        self.in_print = in_print;
+        expr
+    }
+
+    // An expression where an unparenthesized `>` is an output redirect, not a comparison
+    fn print_expression(&mut self) -> Expr {
+        self.in_print = true;
+        let expr = self.assignment();
+        self.in_print = false;
+        expr
+    }
+
+    // Assignment is right associative so a = b = 1 assigns 1 to both
+    fn assignment(&mut self) -> Expr {
+        let expr = self.ternary();
+        if self.matches(vec![TokenType::Eq]) {
+            return Expr::Assign(Box::new(Parser::lvalue(expr)), Box::new(self.assignment()));
+        }
+        if self.matches(vec![TokenType::AssignOp]) {
+            let op = match self.previous().unwrap() {
+                Token::AssignOp(op) => op,
+                _ => panic!("Parser bug in assign op"),
+            };
+            return Expr::AssignOp(Box::new(Parser::lvalue(expr)), op, Box::new(self.assignment()));
+        }
+        expr
+    }
+
+    // a ? b : c ? d : e is a ? b : (c ? d : e)
+    fn ternary(&mut self) -> Expr {
+        let test = self.logical_or();
+        if self.matches(vec![TokenType::Question]) {
+            self.skip_newlines();
+            let if_true = self.assignment();
+            self.skip_newlines();
+            self.consume(TokenType::Colon, "Expected : after the true branch of ?");
+            self.skip_newlines();
+            let if_false = self.assignment();
+            return Expr::Ternary(Box::new(test), Box::new(if_true), Box::new(if_false));
+        }
+        test
+    }
+
+    fn logical_or(&mut self) -> Expr {
+        let mut expr = self.logical_and();
+        while self.matches(vec![TokenType::Or]) {
+            self.skip_newlines();
+            expr = Expr::LogicalOp(Box::new(expr), LogicalOp::Or, Box::new(self.logical_and()));
+        }
+        expr
+    }
+
+    fn logical_and(&mut self) -> Expr {
+        let mut expr = self.in_array();
+        while self.matches(vec![TokenType::And]) {
+            self.skip_newlines();
+            expr = Expr::LogicalOp(Box::new(expr), LogicalOp::And, Box::new(self.in_array()));
+        }
+        expr
+    }
+
+    fn in_array(&mut self) -> Expr {
+        let mut expr = self.regex_match();
+        while self.matches(vec![TokenType::In]) {
+            let name = self.ident("Expected an array name after 'in'");
+            expr = Expr::InArray(vec![expr], name);
         }
-        self.compare()
+        expr
+    }
+
+    fn regex_match(&mut self) -> Expr {
+        let mut expr = self.compare();
+        while self.matches(vec![TokenType::Matches, TokenType::NotMatches]) {
+            let op = match self.previous().unwrap() {
+                Token::BinOp(BinOp::Matches) => BinOp::Matches,
+                Token::BinOp(BinOp::NotMatches) => BinOp::NotMatches,
+                _ => panic!("Parser bug in regex_match function"),
+            };
+            expr = Expr::BinOp(Box::new(expr), op, Box::new(self.compare()))
+        }
+        expr
     }
 
     fn compare(&mut self) -> Expr {
-        let mut expr = self.comparison();
-        while self.matches(vec![TokenType::GreaterEq, TokenType::Greater, TokenType::Less, TokenType::LessEq, TokenType::EqEq, TokenType::BangEq]) {
+        let mut expr = self.pipe_getline();
+        while !(self.in_print && self.check(TokenType::Greater)) &&
+            self.matches(vec![TokenType::GreaterEq, TokenType::Greater, TokenType::Less, TokenType::LessEq, TokenType::EqEq, TokenType::BangEq]) {
             let op = match self.previous().unwrap() {
                 Token::BinOp(BinOp::Less) => BinOp::Less,
                 Token::BinOp(BinOp::LessEq) => BinOp::LessEq,
@@ -213,11 +541,53 @@ impl Parser {
                 Token::BinOp(BinOp::EqEq) => BinOp::EqEq,
                 _ => panic!("Parser bug in compare matches function"),
             };
-            expr = Expr::BinOp(Box::new(expr), op, Box::new(self.comparison()))
+            expr = Expr::BinOp(Box::new(expr), op, Box::new(self.pipe_getline()))
+        }
+        expr
+    }
+
+    // cmd | getline [var]. The command is a whole concatenation eg. "sort " file | getline
+    fn pipe_getline(&mut self) -> Expr {
+        let mut expr = self.concatenation();
+        while self.check(TokenType::Pipe) && self.peek_next().ttype() == TokenType::Getline {
+            self.advance();
+            self.advance();
+            let lvalue = self.getline_lvalue();
+            expr = Expr::Getline(GetlineSource::Command(Box::new(expr)), lvalue);
         }
         expr
     }
 
+    // The optional variable, field or array element after getline
+    fn getline_lvalue(&mut self) -> Option<Box<LValue>> {
+        if self.check(TokenType::Ident) || self.check(TokenType::Column) {
+            Some(Box::new(Parser::lvalue(self.primary())))
+        } else {
+            None
+        }
+    }
+
+    // Juxtaposition eg. `$1 " - " $2`. Binds tighter than comparisons and looser than + and -.
+    fn concatenation(&mut self) -> Expr {
+        let expr = self.comparison();
+        if !self.starts_operand() {
+            return expr;
+        }
+        let mut parts = vec![expr];
+        while self.starts_operand() {
+            parts.push(self.comparison());
+        }
+        Expr::Concat(parts)
+    }
+
+    // Tokens which can begin an operand of concatenation. '-' and '+' are excluded since
+    // `a -1` is subtraction.
+    fn starts_operand(&mut self) -> bool {
+        matches!(self.peek(),
+            Token::NumberF64(_) | Token::String(_) | Token::Regex(_) | Token::Ident(_) | Token::Call(_) |
+            Token::Column | Token::LeftParen | Token::Bang | Token::Increment | Token::Decrement)
+    }
+
     fn comparison(&mut self) -> Expr {
         let mut expr = self.term();
         while self.matches(vec![TokenType::Plus, TokenType::Minus]) {
@@ -232,18 +602,76 @@ impl Parser {
     }
 
     fn term(&mut self) -> Expr {
-        let mut expr = self.primary();
-        while self.matches(vec![TokenType::Star, TokenType::Slash]) {
+        let mut expr = self.unary();
+        while self.matches(vec![TokenType::Star, TokenType::Slash, TokenType::Modulo]) {
             let op = match self.previous().unwrap() {
                 Token::BinOp(BinOp::Star) => BinOp::Star,
                 Token::BinOp(BinOp::Slash) => BinOp::Slash,
-                _ => panic!("Parser bug in comparison function")
+                Token::BinOp(BinOp::Modulo) => BinOp::Modulo,
+                _ => panic!("Parser bug in term function")
             };
-            expr = Expr::BinOp(Box::new(expr), op, Box::new(self.primary()))
+            expr = Expr::BinOp(Box::new(expr), op, Box::new(self.unary()))
+        }
+        expr
+    }
+
+    fn unary(&mut self) -> Expr {
+        if self.matches(vec![TokenType::Bang]) {
+            return Expr::Not(Box::new(self.unary()));
+        }
+        if self.matches(vec![TokenType::Minus]) {
+            return Expr::UnaryMinus(Box::new(self.unary()));
+        }
+        if self.matches(vec![TokenType::Plus]) {
+            return Expr::UnaryPlus(Box::new(self.unary()));
+        }
+        self.power()
+    }
+
+    // ^ is right associative and binds tighter than unary minus. -2^2 is -4 and 2^3^2 is 512
+    fn power(&mut self) -> Expr {
+        let expr = self.increment();
+        if self.matches(vec![TokenType::Pow]) {
+            // The exponent may itself be negated eg. 2^-1
+            return Expr::BinOp(Box::new(expr), BinOp::Pow, Box::new(self.unary()));
+        }
+        expr
+    }
+
+    fn increment(&mut self) -> Expr {
+        if self.matches(vec![TokenType::Increment, TokenType::Decrement]) {
+            let delta = if self.previous().unwrap() == Token::Increment { 1.0 } else { -1.0 };
+            let expr = self.primary();
+            return Expr::PreIncrement(Box::new(Parser::lvalue(expr)), delta);
+        }
+        let expr = self.primary();
+        if Parser::is_lvalue(&expr) && self.matches(vec![TokenType::Increment, TokenType::Decrement]) {
+            let delta = if self.previous().unwrap() == Token::Increment { 1.0 } else { -1.0 };
+            return Expr::PostIncrement(Box::new(Parser::lvalue(expr)), delta);
         }
         expr
     }
 
+    // The operand of $ may have prefix operators, eg. $-1 or $++i. A postfix ++ or -- applies to
+    // the field so $i++ is ($i)++.
+    fn column_operand(&mut self) -> Expr {
+        if self.matches(vec![TokenType::Increment, TokenType::Decrement]) {
+            let delta = if self.previous().unwrap() == Token::Increment { 1.0 } else { -1.0 };
+            let expr = self.primary();
+            return Expr::PreIncrement(Box::new(Parser::lvalue(expr)), delta);
+        }
+        if self.matches(vec![TokenType::Minus]) {
+            return Expr::UnaryMinus(Box::new(self.column_operand()));
+        }
+        if self.matches(vec![TokenType::Plus]) {
+            return Expr::UnaryPlus(Box::new(self.column_operand()));
+        }
+        if self.matches(vec![TokenType::Bang]) {
+            return Expr::Not(Box::new(self.column_operand()));
+        }
+        self.primary()
+    }
+
     fn primary(&mut self) -> Expr {
         if self.is_at_end() {
             panic!("Primary and at end")
@@ -253,42 +681,99 @@ impl Parser {
                 self.advance();
                 Expr::NumberF64(num)
             }
+            Token::Column => {
+                // $ binds tighter than any binary operator. $1+2 is ($1)+2
+                self.consume(TokenType::Column, "Expected to parse a $ here");
+                Expr::Column(Box::new(self.column_operand()))
+            }
             Token::LeftParen => {
                 self.consume(TokenType::LeftParen, "Expected to parse a left paren here");
                 let expr = self.expression();
+                if self.matches(vec![TokenType::Comma]) {
+                    // (a, b) in array
+                    let mut indices = vec![expr, self.expression()];
+                    while self.matches(vec![TokenType::Comma]) {
+                        indices.push(self.expression());
+                    }
+                    self.consume(TokenType::RightParen, "Missing closing ')' after (a, b) in array");
+                    self.consume(TokenType::In, "Expected 'in' after (a, b)");
+                    let name = self.ident("Expected an array name after 'in'");
+                    return Expr::InArray(indices, name);
+                }
                 self.consume(TokenType::RightParen, "Missing closing ')' after group");
                 expr
             }
             Token::Ident(name) => {
                 self.consume(TokenType::Ident, "Expected to parse an ident here");
+                if let Some(builtin) = Builtin::from_name(&name) {
+                    if builtin == Builtin::Length && !self.check(TokenType::LeftParen) {
+                        // `length` alone is the length of $0
+                        return Expr::CallBuiltin(builtin, vec![]);
+                    }
+                    // Unlike user functions built-ins allow a space before the '('
+                    self.consume(TokenType::LeftParen, &format!("Expected '(' after {}", name));
+                    let args = self.call_args();
+                    return Parser::builtin_call(builtin, args);
+                }
+                if self.check(TokenType::LeftBracket) {
+                    return Expr::ArrayIndex(name, self.subscripts());
+                }
                 Expr::Variable(name)
             }
+            Token::Getline => {
+                self.advance();
+                let lvalue = self.getline_lvalue();
+                let source = if self.matches(vec![TokenType::Less]) {
+                    // The file is a single operand. getline < "a" "b" is (getline < "a") "b"
+                    GetlineSource::File(Box::new(self.increment()))
+                } else {
+                    GetlineSource::Main
+                };
+                Expr::Getline(source, lvalue)
+            }
+            Token::Call(name) => {
+                self.consume(TokenType::Call, "Expected to parse a function name here");
+                self.consume(TokenType::LeftParen, "Expected '(' after function name");
+                let args = self.call_args();
+                match Builtin::from_name(&name) {
+                    Some(builtin) => Parser::builtin_call(builtin, args),
+                    None => Expr::Call(name, args),
+                }
+            }
             Token::String(string) => {
                 self.consume(TokenType::String, "Expected to parse a string here");
                 Expr::String(string)
             }
+            Token::Regex(regex) => {
+                self.consume(TokenType::Regex, "Expected to parse a regex here");
+                Expr::Regex(regex)
+            }
             t => panic!("Unexpected token {:?} {}", t, TokenType::name(t.ttype()))
         }
     }
 }
 
+#[cfg(test)]
 macro_rules! num {
     ($value:expr) => {
         Expr::NumberF64($value)
     }
 }
+#[cfg(test)]
 macro_rules! bnum {
     ($value:expr) => {
         Box::new(Expr::NumberF64($value))
     }
 }
 
+#[cfg(test)]
 macro_rules! sprogram {
     ($body:expr) => {
         Program::new(vec![], vec![], vec![PatternAction::new_action_only($body)])
     }
 }
 
+#[cfg(test)]
 macro_rules! actual {
     ($name:ident, $body:expr) => {
         use crate::lexer::lex;
@@ -329,7 +814,7 @@ fn test_ast_oop_2() {
 #[test]
 fn test_ast_assign() {
     use crate::lexer::lex;
-    let stmt = Stmt::Assign(format!("abc"), Expr::NumberF64(2.0));
+    let stmt = Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("abc".to_string())), Box::new(Expr::NumberF64(2.0))));
     assert_eq!(parse(lex("{abc = 2.0; }").unwrap()), Program::new_action_only(stmt));
 }
 
@@ -366,14 +851,14 @@ fn test_if_only() {
 fn test_print() {
     use crate::lexer::lex;
     let str = "{print 1;}";
-    assert_eq!(parse(lex(str).unwrap()), Program::new_action_only(Stmt::Print(Expr::NumberF64(1.0))));
+    assert_eq!(parse(lex(str).unwrap()), Program::new_action_only(Stmt::Print(Expr::NumberF64(1.0), None)));
 }
 
 #[test]
 fn test_group() {
     use crate::lexer::lex;
     let str = "{{print 1; print 2;}}";
-    assert_eq!(parse(lex(str).unwrap()), Program::new_action_only(Stmt::Group(vec![Stmt::Print(Expr::NumberF64(1.0)), Stmt::Print(Expr::NumberF64(2.0))])));
+    assert_eq!(parse(lex(str).unwrap()), Program::new_action_only(Stmt::Group(vec![Stmt::Print(Expr::NumberF64(1.0), None), Stmt::Print(Expr::NumberF64(2.0), None)])));
 }
 
 
@@ -396,9 +881,9 @@ fn test_paser_begin_end() {
     use crate::lexer::lex;
     let str = "a { print 5; } BEGIN { print 1; } begin { print 2; } END { print 3; } end { print 4; }";
     let actual = parse(lex(str).unwrap());
-    let begins = vec![Stmt::Print(Expr::NumberF64(1.0)), Stmt::Print(Expr::NumberF64(2.0))];
-    let ends = vec![Stmt::Print(Expr::NumberF64(3.0)), Stmt::Print(Expr::NumberF64(4.0))];
-    let generic = PatternAction::new(Some(Expr::Variable("a".to_string())), Stmt::Print(Expr::NumberF64(5.0)));
+    let begins = vec![Stmt::Print(Expr::NumberF64(1.0), None), Stmt::Print(Expr::NumberF64(2.0), None)];
+    let ends = vec![Stmt::Print(Expr::NumberF64(3.0), None), Stmt::Print(Expr::NumberF64(4.0), None)];
+    let generic = PatternAction::new(Some(Expr::Variable("a".to_string())), Stmt::Print(Expr::NumberF64(5.0), None));
     assert_eq!(actual, Program::new(begins, ends, vec![generic]));
 }
 
@@ -406,7 +891,7 @@ fn test_paser_begin_end() {
 fn test_parser_begin_end2() {
     use crate::lexer::lex;
     let str = "a { print 5; }";
-    let actual = parse(lex(str).unwrap());
+    parse(lex(str).unwrap());
 }
 
 #[test]
@@ -422,7 +907,7 @@ fn test_print_no_semicolon() {
     use crate::lexer::lex;
     let str = "{ print 1 }";
     let actual = parse(lex(str).unwrap());
-    assert_eq!(actual, Program::new(vec![], vec![], vec![PatternAction::new_action_only(Stmt::Print(Expr::NumberF64(1.0)))]));
+    assert_eq!(actual, Program::new(vec![], vec![], vec![PatternAction::new_action_only(Stmt::Print(Expr::NumberF64(1.0), None))]));
 }
 
 #[test]
@@ -430,8 +915,8 @@ fn test_column() {
     use crate::lexer::lex;
     let str = "$0+2 { print a; }";
     let actual = parse(lex(str).unwrap());
-    let body = Stmt::Print(Expr::Variable("a".to_string()));
-    let pattern = Expr::Column(Box::new(Expr::BinOp(bnum!(0.0), BinOp::Plus, bnum!(2.0))));
+    let body = Stmt::Print(Expr::Variable("a".to_string()), None);
+    let pattern = Expr::BinOp(Box::new(Expr::Column(bnum!(0.0))), BinOp::Plus, bnum!(2.0));
     let pa = PatternAction::new(Some(pattern), body);
     assert_eq!(actual, Program::new(vec![], vec![], vec![pa]));
 }
@@ -441,7 +926,7 @@ fn test_while_l00p() {
     use crate::lexer::lex;
     let str = "{ while (123) { print 1; } }";
     let actual = parse(lex(str).unwrap());
-    let body = Stmt::While(Expr::NumberF64(123.0), Box::new(Stmt::Print(Expr::NumberF64(1.0))));
+    let body = Stmt::While(Expr::NumberF64(123.0), Box::new(Stmt::Print(Expr::NumberF64(1.0), None)));
     assert_eq!(actual, Program::new(vec![], vec![], vec![PatternAction::new_action_only(body)]));
 }
 
@@ -510,6 +995,435 @@ fn test_cmp_oop2() {
 
     let left = Expr::BinOp(bnum!(1.0), BinOp::Star, bnum!(3.0));
     let body = Expr::BinOp(Box::new(left), BinOp::EqEq, bnum!(4.0));
-    let stmt = Stmt::Assign(format!("a"), body);
+    let stmt = Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("a".to_string())), Box::new(body)));
     assert_eq!(actual, sprogram!(stmt));
-}
\ No newline at end of file
+}
+#[test]
+fn test_regex_pattern() {
+    actual!(actual, "/abc/ { print 1 }");
+    let pa = PatternAction::new(Some(Expr::Regex("abc".to_string())), Stmt::Print(num!(1.0), None));
+    assert_eq!(actual, Program::new(vec![], vec![], vec![pa]));
+}
+
+#[test]
+fn test_regex_pattern_only() {
+    actual!(actual, "/abc/");
+    assert_eq!(actual, Program::new(vec![], vec![], vec![PatternAction::new_pattern_only(Expr::Regex("abc".to_string()))]));
+}
+
+#[test]
+fn test_print_no_args() {
+    actual!(actual, "{ print }");
+    assert_eq!(actual, sprogram!(Stmt::Print(Expr::Column(bnum!(0.0)), None)));
+}
+
+#[test]
+fn test_match_regex_literal() {
+    actual!(actual, "{ a ~ /abc/ }");
+    let body = Stmt::Expr(Expr::BinOp(Box::new(Expr::Variable("a".to_string())), BinOp::Matches, Box::new(Expr::Regex("abc".to_string()))));
+    assert_eq!(actual, sprogram!(body));
+}
+
+#[test]
+fn test_not_match_dynamic() {
+    actual!(actual, "{ a !~ \"x\" }");
+    let body = Stmt::Expr(Expr::BinOp(Box::new(Expr::Variable("a".to_string())), BinOp::NotMatches, Box::new(Expr::String("x".to_string()))));
+    assert_eq!(actual, sprogram!(body));
+}
+
+#[test]
+fn test_match_lower_than_compare() {
+    actual!(actual, "{ 1 < 2 ~ 3 }");
+    let left = Expr::BinOp(bnum!(1.0), BinOp::Less, bnum!(2.0));
+    let body = Stmt::Expr(Expr::BinOp(Box::new(left), BinOp::Matches, bnum!(3.0)));
+    assert_eq!(actual, sprogram!(body));
+}
+
+#[test]
+fn test_column_grouped() {
+    actual!(actual, "{ print $(1+2) }");
+    let body = Stmt::Print(Expr::Column(Box::new(Expr::BinOp(bnum!(1.0), BinOp::Plus, bnum!(2.0)))), None);
+    assert_eq!(actual, sprogram!(body));
+}
+
+#[test]
+fn test_column_match() {
+    actual!(actual, "$2 ~ /a/");
+    let pattern = Expr::BinOp(Box::new(Expr::Column(bnum!(2.0))), BinOp::Matches, Box::new(Expr::Regex("a".to_string())));
+    assert_eq!(actual, Program::new(vec![], vec![], vec![PatternAction::new_pattern_only(pattern)]));
+}
+
+#[test]
+fn test_range_pattern() {
+    actual!(actual, "/a/, /b/ { print 1 }");
+    let pa = PatternAction::new_range(Expr::Regex("a".to_string()), Expr::Regex("b".to_string()), Stmt::Print(num!(1.0), None));
+    assert_eq!(actual, Program::new(vec![], vec![], vec![pa]));
+}
+
+#[test]
+fn test_range_pattern_only() {
+    actual!(actual, "$1 == 1, $1 == 2");
+    let start = Expr::BinOp(Box::new(Expr::Column(bnum!(1.0))), BinOp::EqEq, bnum!(1.0));
+    let end = Expr::BinOp(Box::new(Expr::Column(bnum!(1.0))), BinOp::EqEq, bnum!(2.0));
+    assert_eq!(actual, Program::new(vec![], vec![], vec![PatternAction::new_range_only(start, end)]));
+}
+
+#[test]
+fn test_assign_column() {
+    actual!(actual, "{ $1 = $2 }");
+    let stmt = Stmt::Expr(Expr::Assign(Box::new(LValue::Column(num!(1.0))), Box::new(Expr::Column(bnum!(2.0)))));
+    assert_eq!(actual, sprogram!(stmt));
+}
+
+#[test]
+fn test_assign_computed_column() {
+    actual!(actual, "{ $(i+1) = 2 }");
+    let index = Expr::BinOp(Box::new(Expr::Variable("i".to_string())), BinOp::Plus, bnum!(1.0));
+    let stmt = Stmt::Expr(Expr::Assign(Box::new(LValue::Column(index)), bnum!(2.0)));
+    assert_eq!(actual, sprogram!(stmt));
+}
+
+#[test]
+fn test_array_assign() {
+    actual!(actual, "{ a[1] = a[1, 2] }");
+    let stmt = Stmt::Expr(Expr::Assign(Box::new(LValue::ArrayIndex("a".to_string(), vec![num!(1.0)])), Box::new(Expr::ArrayIndex("a".to_string(), vec![num!(1.0), num!(2.0)]))));
+    assert_eq!(actual, sprogram!(stmt));
+}
+
+#[test]
+fn test_in_array() {
+    actual!(actual, "{ print 1 in a; print (1, 2) in a }");
+    let single = Stmt::Print(Expr::InArray(vec![num!(1.0)], "a".to_string()), None);
+    let multi = Stmt::Print(Expr::InArray(vec![num!(1.0), num!(2.0)], "a".to_string()), None);
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![single, multi])));
+}
+
+#[test]
+fn test_in_lower_than_compare() {
+    actual!(actual, "{ 1 < 2 in a }");
+    let cmp = Expr::BinOp(bnum!(1.0), BinOp::Less, bnum!(2.0));
+    assert_eq!(actual, sprogram!(Stmt::Expr(Expr::InArray(vec![cmp], "a".to_string()))));
+}
+
+#[test]
+fn test_delete() {
+    actual!(actual, "{ delete a[1]; delete a }");
+    let one = Stmt::Delete("a".to_string(), Some(vec![num!(1.0)]));
+    let all = Stmt::Delete("a".to_string(), None);
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![one, all])));
+}
+
+#[test]
+fn test_for_in() {
+    actual!(actual, "{ for (k in a) { print k } }");
+    let body = Stmt::Print(Expr::Variable("k".to_string()), None);
+    assert_eq!(actual, sprogram!(Stmt::ForIn("k".to_string(), "a".to_string(), Box::new(body))));
+}
+
+#[test]
+fn test_function_def() {
+    actual!(actual, "function add(a, b) { return a + b } BEGIN { print add(1, 2) }");
+    let body = Stmt::Return(Some(Expr::BinOp(Box::new(Expr::Variable("a".to_string())), BinOp::Plus, Box::new(Expr::Variable("b".to_string())))));
+    let func = Function::new("add".to_string(), vec![format!("a"), format!("b")], body);
+    let begin = Stmt::Print(Expr::Call("add".to_string(), vec![num!(1.0), num!(2.0)]), None);
+    assert_eq!(actual, Program::new_with_functions(vec![begin], vec![], vec![], vec![func]));
+}
+
+#[test]
+fn test_function_no_args() {
+    actual!(actual, "function f() { } BEGIN { f() }");
+    let func = Function::new("f".to_string(), vec![], Stmt::Group(vec![]));
+    let begin = Stmt::Expr(Expr::Call("f".to_string(), vec![]));
+    assert_eq!(actual, Program::new_with_functions(vec![begin], vec![], vec![], vec![func]));
+}
+
+#[test]
+fn test_printf() {
+    actual!(actual, "{ printf \"%d %s\", 1, $2 }");
+    let args = vec![num!(1.0), Expr::Column(bnum!(2.0))];
+    assert_eq!(actual, sprogram!(Stmt::Printf(Expr::String("%d %s".to_string()), args, None)));
+}
+
+#[test]
+fn test_printf_parens() {
+    actual!(actual, "{ printf(\"%d\", 1) }");
+    assert_eq!(actual, sprogram!(Stmt::Printf(Expr::String("%d".to_string()), vec![num!(1.0)], None)));
+}
+
+#[test]
+fn test_sprintf() {
+    actual!(actual, "{ x = sprintf (\"%c\", 65) }");
+    let call = Expr::CallBuiltin(Builtin::Sprintf, vec![Expr::String("%c".to_string()), num!(65.0)]);
+    assert_eq!(actual, sprogram!(Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("x".to_string())), Box::new(call)))));
+}
+
+#[test]
+fn test_concat() {
+    actual!(actual, "{ print $1 \" - \" $2 }");
+    let concat = Expr::Concat(vec![Expr::Column(bnum!(1.0)), Expr::String(" - ".to_string()), Expr::Column(bnum!(2.0))]);
+    assert_eq!(actual, sprogram!(Stmt::Print(concat, None)));
+}
+
+#[test]
+fn test_concat_precedence() {
+    actual!(actual, "{ print 1 + 2 3 < 4 }");
+    let sum = Expr::BinOp(bnum!(1.0), BinOp::Plus, bnum!(2.0));
+    let concat = Expr::Concat(vec![sum, num!(3.0)]);
+    assert_eq!(actual, sprogram!(Stmt::Print(Expr::BinOp(Box::new(concat), BinOp::Less, bnum!(4.0)), None)));
+}
+
+#[test]
+fn test_newline_separates_statements() {
+    actual!(actual, "{ x = 1\n y = 2 }");
+    let x = Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("x".to_string())), bnum!(1.0)));
+    let y = Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("y".to_string())), bnum!(2.0)));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![x, y])));
+}
+
+#[test]
+fn test_newline_pattern_then_action() {
+    actual!(actual, "$1\n{ print 2 }");
+    let pattern = PatternAction::new_pattern_only(Expr::Column(bnum!(1.0)));
+    let action = PatternAction::new_action_only(Stmt::Print(num!(2.0), None));
+    assert_eq!(actual, Program::new(vec![], vec![], vec![pattern, action]));
+}
+
+#[test]
+fn test_logical_precedence() {
+    actual!(actual, "{ print 1 || 2 && !3 }");
+    let not = Expr::Not(bnum!(3.0));
+    let and = Expr::LogicalOp(bnum!(2.0), LogicalOp::And, Box::new(not));
+    assert_eq!(actual, sprogram!(Stmt::Print(Expr::LogicalOp(bnum!(1.0), LogicalOp::Or, Box::new(and)), None)));
+}
+
+#[test]
+fn test_logical_below_compare() {
+    actual!(actual, "{ print 1 < 2 && 3 ~ 4 }");
+    let less = Expr::BinOp(bnum!(1.0), BinOp::Less, bnum!(2.0));
+    let matches = Expr::BinOp(bnum!(3.0), BinOp::Matches, bnum!(4.0));
+    assert_eq!(actual, sprogram!(Stmt::Print(Expr::LogicalOp(Box::new(less), LogicalOp::And, Box::new(matches)), None)));
+}
+
+#[test]
+fn test_unary_minus() {
+    actual!(actual, "{ print x-5; print -x }");
+    let x = Box::new(Expr::Variable("x".to_string()));
+    let sub = Stmt::Print(Expr::BinOp(x.clone(), BinOp::Minus, bnum!(5.0)), None);
+    let neg = Stmt::Print(Expr::UnaryMinus(x), None);
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![sub, neg])));
+}
+
+#[test]
+fn test_pow_right_assoc() {
+    actual!(actual, "{ print -2 ^ 3 ^ 2 }");
+    let pow = Expr::BinOp(bnum!(2.0), BinOp::Pow, Box::new(Expr::BinOp(bnum!(3.0), BinOp::Pow, bnum!(2.0))));
+    assert_eq!(actual, sprogram!(Stmt::Print(Expr::UnaryMinus(Box::new(pow)), None)));
+}
+
+#[test]
+fn test_modulo() {
+    actual!(actual, "{ print 1 + 7 % 3 }");
+    let modulo = Expr::BinOp(bnum!(7.0), BinOp::Modulo, bnum!(3.0));
+    assert_eq!(actual, sprogram!(Stmt::Print(Expr::BinOp(bnum!(1.0), BinOp::Plus, Box::new(modulo)), None)));
+}
+
+#[test]
+fn test_increments() {
+    actual!(actual, "{ x++; --$1; a[1]++ }");
+    let x = Stmt::Expr(Expr::PostIncrement(Box::new(LValue::Variable("x".to_string())), 1.0));
+    let col = Stmt::Expr(Expr::PreIncrement(Box::new(LValue::Column(num!(1.0))), -1.0));
+    let arr = Stmt::Expr(Expr::PostIncrement(Box::new(LValue::ArrayIndex("a".to_string(), vec![num!(1.0)])), 1.0));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![x, col, arr])));
+}
+
+#[test]
+fn test_assign_op() {
+    actual!(actual, "{ x ^= 2 }");
+    assert_eq!(actual, sprogram!(Stmt::Expr(Expr::AssignOp(Box::new(LValue::Variable("x".to_string())), BinOp::Pow, bnum!(2.0)))));
+}
+
+#[test]
+fn test_assign_chain() {
+    actual!(actual, "{ a = b = 1 }");
+    let inner = Expr::Assign(Box::new(LValue::Variable("b".to_string())), bnum!(1.0));
+    assert_eq!(actual, sprogram!(Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("a".to_string())), Box::new(inner)))));
+}
+
+#[test]
+fn test_assign_in_condition() {
+    actual!(actual, "{ if ((n = 3) > 2) { print n } }");
+    let assign = Expr::Assign(Box::new(LValue::Variable("n".to_string())), bnum!(3.0));
+    let test = Expr::BinOp(Box::new(assign), BinOp::Greater, bnum!(2.0));
+    assert_eq!(actual, sprogram!(Stmt::If(test, Box::new(Stmt::Print(Expr::Variable("n".to_string()), None)), None)));
+}
+
+#[test]
+fn test_ternary() {
+    actual!(actual, "{ x = a ? 1 : b ? 2 : 3 }");
+    let nested = Expr::Ternary(Box::new(Expr::Variable("b".to_string())), bnum!(2.0), bnum!(3.0));
+    let ternary = Expr::Ternary(Box::new(Expr::Variable("a".to_string())), bnum!(1.0), Box::new(nested));
+    assert_eq!(actual, sprogram!(Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("x".to_string())), Box::new(ternary)))));
+}
+
+#[test]
+fn test_for_loop() {
+    actual!(actual, "{ for (i = 0; i < 3; i++) print i }");
+    let init = Expr::Assign(Box::new(LValue::Variable("i".to_string())), bnum!(0.0));
+    let test = Expr::BinOp(Box::new(Expr::Variable("i".to_string())), BinOp::Less, bnum!(3.0));
+    let incr = Expr::PostIncrement(Box::new(LValue::Variable("i".to_string())), 1.0);
+    let body = Stmt::Print(Expr::Variable("i".to_string()), None);
+    assert_eq!(actual, sprogram!(Stmt::For(Some(init), Some(test), Some(incr), Box::new(body))));
+}
+
+#[test]
+fn test_for_loop_empty_parts() {
+    actual!(actual, "{ for (;;) { x } }");
+    let body = Stmt::Expr(Expr::Variable("x".to_string()));
+    assert_eq!(actual, sprogram!(Stmt::For(None, None, None, Box::new(body))));
+}
+
+#[test]
+fn test_do_while() {
+    actual!(actual, "{ do x++; while (x < 3) }");
+    let body = Stmt::Expr(Expr::PostIncrement(Box::new(LValue::Variable("x".to_string())), 1.0));
+    let test = Expr::BinOp(Box::new(Expr::Variable("x".to_string())), BinOp::Less, bnum!(3.0));
+    assert_eq!(actual, sprogram!(Stmt::DoWhile(Box::new(body), test)));
+}
+
+#[test]
+fn test_unbraced_if_else() {
+    actual!(actual, "{ if (x) print 1; else print 2 }");
+    let else_blk = Some(Box::new(Stmt::Print(num!(2.0), None)));
+    assert_eq!(actual, sprogram!(Stmt::If(Expr::Variable("x".to_string()), Box::new(Stmt::Print(num!(1.0), None)), else_blk)));
+}
+
+#[test]
+fn test_unbraced_while_newline() {
+    actual!(actual, "{ while (x)\n x--\n print }");
+    let body = Stmt::Expr(Expr::PostIncrement(Box::new(LValue::Variable("x".to_string())), -1.0));
+    let while_stmt = Stmt::While(Expr::Variable("x".to_string()), Box::new(body));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![while_stmt, Stmt::Print(Expr::Column(bnum!(0.0)), None)])));
+}
+
+#[test]
+fn test_loop_control() {
+    actual!(actual, "{ while (1) { if (x) break; continue } }");
+    let body = Stmt::Group(vec![Stmt::If(Expr::Variable("x".to_string()), Box::new(Stmt::Break), None), Stmt::Continue]);
+    assert_eq!(actual, sprogram!(Stmt::While(num!(1.0), Box::new(body))));
+}
+
+#[test]
+fn test_next_and_exit() {
+    actual!(actual, "{ next; nextfile; exit; exit 1 + 1 }");
+    let exit_code = Expr::BinOp(bnum!(1.0), BinOp::Plus, bnum!(1.0));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![Stmt::Next, Stmt::NextFile, Stmt::Exit(None), Stmt::Exit(Some(exit_code))])));
+}
+
+#[test]
+fn test_getline_forms() {
+    actual!(actual, "{ getline; getline x; getline < \"f\"; getline $1 < f }");
+    let plain = Stmt::Expr(Expr::Getline(GetlineSource::Main, None));
+    let var = Stmt::Expr(Expr::Getline(GetlineSource::Main, Some(Box::new(LValue::Variable("x".to_string())))));
+    let file = Stmt::Expr(Expr::Getline(GetlineSource::File(Box::new(Expr::String("f".to_string()))), None));
+    let field = Stmt::Expr(Expr::Getline(GetlineSource::File(Box::new(Expr::Variable("f".to_string()))), Some(Box::new(LValue::Column(num!(1.0))))));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![plain, var, file, field])));
+}
+
+#[test]
+fn test_getline_command() {
+    actual!(actual, "{ while ((\"ls \" d | getline line) > 0) n++ }");
+    let cmd = Expr::Concat(vec![Expr::String("ls ".to_string()), Expr::Variable("d".to_string())]);
+    let getline = Expr::Getline(GetlineSource::Command(Box::new(cmd)), Some(Box::new(LValue::Variable("line".to_string()))));
+    let test = Expr::BinOp(Box::new(getline), BinOp::Greater, bnum!(0.0));
+    let body = Stmt::Expr(Expr::PostIncrement(Box::new(LValue::Variable("n".to_string())), 1.0));
+    assert_eq!(actual, sprogram!(Stmt::While(test, Box::new(body))));
+}
+
+#[test]
+fn test_print_redirects() {
+    actual!(actual, "{ print $1 > \"out\" i; print >> f; print 1 | \"sort\"; print (1 > 2) > f }");
+    let col1 = Expr::Column(bnum!(1.0));
+    let target = Expr::Concat(vec![Expr::String("out".to_string()), Expr::Variable("i".to_string())]);
+    let line = Expr::Column(bnum!(0.0));
+    let compare = Expr::BinOp(bnum!(1.0), BinOp::Greater, bnum!(2.0));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![
+        Stmt::Print(col1, Some(Redirect::Truncate(target))),
+        Stmt::Print(line, Some(Redirect::Append(Expr::Variable("f".to_string())))),
+        Stmt::Print(num!(1.0), Some(Redirect::Pipe(Expr::String("sort".to_string())))),
+        Stmt::Print(compare, Some(Redirect::Truncate(Expr::Variable("f".to_string())))),
+    ])));
+}
+
+#[test]
+fn test_printf_redirect_and_close() {
+    actual!(actual, "{ printf \"%s\", x[a > b] > \"f\"; close(\"f\"); fflush() }");
+    let index = Expr::ArrayIndex("x".to_string(), vec![Expr::BinOp(Box::new(Expr::Variable("a".to_string())), BinOp::Greater, Box::new(Expr::Variable("b".to_string())))]);
+    let printf = Stmt::Printf(Expr::String("%s".to_string()), vec![index], Some(Redirect::Truncate(Expr::String("f".to_string()))));
+    let close = Stmt::Expr(Expr::CallBuiltin(Builtin::Close, vec![Expr::String("f".to_string())]));
+    let fflush = Stmt::Expr(Expr::CallBuiltin(Builtin::Fflush, vec![]));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![printf, close, fflush])));
+}
+
+#[test]
+fn test_length_without_parens() {
+    actual!(actual, "length > 3 { print length() }");
+    let length = Expr::CallBuiltin(Builtin::Length, vec![]);
+    let pattern = Expr::BinOp(Box::new(length.clone()), BinOp::Greater, bnum!(3.0));
+    let print = Stmt::Print(length.clone(), None);
+    assert_eq!(actual, Program::new(vec![], vec![], vec![PatternAction::new(Some(pattern), print)]));
+}
+
+#[test]
+fn test_string_builtins() {
+    actual!(actual, "{ n = split($0, parts, /,/); gsub(/a/, \"b\", parts[1]); sub(\"x\", \"y\") }");
+    let split = Expr::CallBuiltin(Builtin::Split, vec![Expr::Column(bnum!(0.0)), Expr::Variable("parts".to_string()), Expr::Regex(",".to_string())]);
+    let assign = Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("n".to_string())), Box::new(split)));
+    let element = Expr::ArrayIndex("parts".to_string(), vec![num!(1.0)]);
+    let gsub = Stmt::Expr(Expr::CallBuiltin(Builtin::Gsub, vec![Expr::Regex("a".to_string()), Expr::String("b".to_string()), element]));
+    let sub = Stmt::Expr(Expr::CallBuiltin(Builtin::Sub, vec![Expr::String("x".to_string()), Expr::String("y".to_string())]));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![assign, gsub, sub])));
+}
+
+#[test]
+#[should_panic]
+fn test_sub_target_must_be_lvalue() {
+    actual!(_actual, "{ sub(/a/, \"b\", \"c\") }");
+}
+
+#[test]
+#[should_panic]
+fn test_builtin_arity() {
+    actual!(_actual, "{ substr(\"a\") }");
+}
+
+#[test]
+fn test_math_builtins() {
+    actual!(actual, "{ x = int(rand() * 10) + atan2(0, -1) }");
+    let rand = Expr::CallBuiltin(Builtin::Rand, vec![]);
+    let int = Expr::CallBuiltin(Builtin::Int, vec![Expr::BinOp(Box::new(rand), BinOp::Star, bnum!(10.0))]);
+    let atan2 = Expr::CallBuiltin(Builtin::Atan2, vec![num!(0.0), Expr::UnaryMinus(bnum!(1.0))]);
+    let sum = Expr::BinOp(Box::new(int), BinOp::Plus, Box::new(atan2));
+    assert_eq!(actual, sprogram!(Stmt::Expr(Expr::Assign(Box::new(LValue::Variable("x".to_string())), Box::new(sum)))));
+}
+
+#[test]
+fn test_column_of_expressions() {
+    actual!(actual, "{ print $NF; print $(NF-1); print $NF-1 }");
+    let nf = || Box::new(Expr::Variable("NF".to_string()));
+    let last = Stmt::Print(Expr::Column(nf()), None);
+    let before_last = Stmt::Print(Expr::Column(Box::new(Expr::BinOp(nf(), BinOp::Minus, bnum!(1.0)))), None);
+    let minus = Stmt::Print(Expr::BinOp(Box::new(Expr::Column(nf())), BinOp::Minus, bnum!(1.0)), None);
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![last, before_last, minus])));
+}
+
+#[test]
+fn test_column_prefix_operands() {
+    actual!(actual, "{ $-1; $++i; $i++; $NF--; $ -x + 1 }");
+    let var = |name: &str| Box::new(Expr::Variable(name.to_string()));
+    let negative = Stmt::Expr(Expr::Column(Box::new(Expr::UnaryMinus(bnum!(1.0)))));
+    let pre = Stmt::Expr(Expr::Column(Box::new(Expr::PreIncrement(Box::new(LValue::Variable("i".to_string())), 1.0))));
+    let post = Stmt::Expr(Expr::PostIncrement(Box::new(LValue::Column(Expr::Variable("i".to_string()))), 1.0));
+    let nf = Stmt::Expr(Expr::PostIncrement(Box::new(LValue::Column(Expr::Variable("NF".to_string()))), -1.0));
+    let sum = Stmt::Expr(Expr::BinOp(Box::new(Expr::Column(Box::new(Expr::UnaryMinus(var("x"))))), BinOp::Plus, bnum!(1.0)));
+    assert_eq!(actual, sprogram!(Stmt::Group(vec![negative, pre, post, nf, sum])));
+}
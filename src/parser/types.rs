@@ -4,62 +4,283 @@ use crate::lexer::{BinOp, LogicalOp};
 #[derive(Debug, PartialEq)]
 pub enum Stmt {
     Expr(Expr),
-    Print(Expr),
-    Assign(String, Expr),
+    Print(Expr, Option<Redirect>),
+    // printf format, args...
+    Printf(Expr, Vec<Expr>, Option<Redirect>),
     Return(Option<Expr>),
     Group(Vec<Stmt>),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
-    While(Expr, Box<Stmt>)
+    While(Expr, Box<Stmt>),
+    // do body while (test)
+    DoWhile(Box<Stmt>, Expr),
+    // for (init; test; incr) body. A missing test is always true.
+    For(Option<Expr>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    // for (var in array) body
+    ForIn(String, String, Box<Stmt>),
+    // delete array[subscripts] or delete array when None
+    Delete(String, Option<Vec<Expr>>),
+    Break,
+    Continue,
+    // Stop processing the current record and move to the next one
+    Next,
+    // Stop processing the current file and move to the next one
+    NextFile,
+    // Run the END blocks (unless already in one) then exit with the status
+    Exit(Option<Expr>),
+}
+
+// Where print and printf send their output instead of stdout
+#[derive(Debug, PartialEq)]
+pub enum Redirect {
+    // > file. Truncates the file when it is first opened.
+    Truncate(Expr),
+    // >> file
+    Append(Expr),
+    // | command
+    Pipe(Expr),
+}
+
+// Anything that can appear on the left of an `=`
+#[derive(Debug, PartialEq, Clone)]
+pub enum LValue {
+    Variable(String),
+    Column(Expr),
+    ArrayIndex(String, Vec<Expr>),
+}
+
+impl Display for LValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LValue::Variable(name) => write!(f, "{}", name),
+            LValue::Column(col) => write!(f, "${}", col),
+            LValue::ArrayIndex(name, indices) => write!(f, "{}[{}]", name, indices.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Pattern {
+    Expr(Expr),
+    // pat1, pat2 matches from a line matching pat1 through the next line matching pat2
+    Range(Expr, Expr),
 }
 
 #[derive(Debug, PartialEq)]
 pub struct PatternAction {
-    pub pattern: Option<Expr>,
+    pub pattern: Option<Pattern>,
     pub action: Stmt
 }
 impl PatternAction {
     pub fn new(pattern: Option<Expr>, action: Stmt) -> Self {
-        Self { pattern, action }
+        Self { pattern: pattern.map(Pattern::Expr), action }
     }
-    pub fn new_pattern_only(test: Expr) -> PatternAction { PatternAction::new(Some(test), Stmt::Print(Expr::Column(Box::new(Expr::NumberF64(0.0))))) }
+    pub fn new_range(start: Expr, end: Expr, action: Stmt) -> Self {
+        Self { pattern: Some(Pattern::Range(start, end)), action }
+    }
+    pub fn new_pattern_only(test: Expr) -> PatternAction { PatternAction::new(Some(test), PatternAction::print_line()) }
+    pub fn new_range_only(start: Expr, end: Expr) -> PatternAction { PatternAction::new_range(start, end, PatternAction::print_line()) }
     pub fn new_action_only(body: Stmt) -> PatternAction { PatternAction::new(None, body) }
+    fn print_line() -> Stmt { Stmt::Print(Expr::Column(Box::new(Expr::NumberF64(0.0))), None) }
+}
+
+
+// Functions provided by the runtime rather than defined in the program
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Builtin {
+    Sprintf,
+    Close,
+    Fflush,
+    Length,
+    Substr,
+    Index,
+    Split,
+    Sub,
+    Gsub,
+    Match,
+    Tolower,
+    Toupper,
+    Int,
+    Sqrt,
+    Exp,
+    Log,
+    Sin,
+    Cos,
+    Atan2,
+    Rand,
+    Srand,
 }
 
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "sprintf" => Some(Builtin::Sprintf),
+            "close" => Some(Builtin::Close),
+            "fflush" => Some(Builtin::Fflush),
+            "length" => Some(Builtin::Length),
+            "substr" => Some(Builtin::Substr),
+            "index" => Some(Builtin::Index),
+            "split" => Some(Builtin::Split),
+            "sub" => Some(Builtin::Sub),
+            "gsub" => Some(Builtin::Gsub),
+            "match" => Some(Builtin::Match),
+            "tolower" => Some(Builtin::Tolower),
+            "toupper" => Some(Builtin::Toupper),
+            "int" => Some(Builtin::Int),
+            "sqrt" => Some(Builtin::Sqrt),
+            "exp" => Some(Builtin::Exp),
+            "log" => Some(Builtin::Log),
+            "sin" => Some(Builtin::Sin),
+            "cos" => Some(Builtin::Cos),
+            "atan2" => Some(Builtin::Atan2),
+            "rand" => Some(Builtin::Rand),
+            "srand" => Some(Builtin::Srand),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Sprintf => "sprintf",
+            Builtin::Close => "close",
+            Builtin::Fflush => "fflush",
+            Builtin::Length => "length",
+            Builtin::Substr => "substr",
+            Builtin::Index => "index",
+            Builtin::Split => "split",
+            Builtin::Sub => "sub",
+            Builtin::Gsub => "gsub",
+            Builtin::Match => "match",
+            Builtin::Tolower => "tolower",
+            Builtin::Toupper => "toupper",
+            Builtin::Int => "int",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Exp => "exp",
+            Builtin::Log => "log",
+            Builtin::Sin => "sin",
+            Builtin::Cos => "cos",
+            Builtin::Atan2 => "atan2",
+            Builtin::Rand => "rand",
+            Builtin::Srand => "srand",
+        }
+    }
+    // The fewest and most arguments the builtin accepts
+    pub fn arity(&self) -> (usize, usize) {
+        match self {
+            Builtin::Sprintf => (1, usize::MAX),
+            Builtin::Close | Builtin::Tolower | Builtin::Toupper => (1, 1),
+            Builtin::Int | Builtin::Sqrt | Builtin::Exp | Builtin::Log | Builtin::Sin | Builtin::Cos => (1, 1),
+            Builtin::Fflush | Builtin::Length | Builtin::Srand => (0, 1),
+            Builtin::Substr | Builtin::Split | Builtin::Sub | Builtin::Gsub => (2, 3),
+            Builtin::Index | Builtin::Match | Builtin::Atan2 => (2, 2),
+            Builtin::Rand => (0, 0),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     NumberF64(f64),
     String(String),
+    Regex(String),
     BinOp(Box<Expr>, BinOp, Box<Expr>),
+    // a b c
+    Concat(Vec<Expr>),
     LogicalOp(Box<Expr>, LogicalOp, Box<Expr>),
+    // lvalue = expr, evaluates to the assigned value
+    Assign(Box<LValue>, Box<Expr>),
+    // lvalue op= expr eg. `x += 2`
+    AssignOp(Box<LValue>, BinOp, Box<Expr>),
+    // test ? if_true : if_false
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    UnaryMinus(Box<Expr>),
+    UnaryPlus(Box<Expr>),
+    // ++x is PreIncrement(x, 1) and --x is PreIncrement(x, -1)
+    PreIncrement(Box<LValue>, f64),
+    // x++ is PostIncrement(x, 1) and x-- is PostIncrement(x, -1)
+    PostIncrement(Box<LValue>, f64),
     Variable(String),
     Column(Box<Expr>),
-    Call,
+    // array[a, b, c]
+    ArrayIndex(String, Vec<Expr>),
+    // (a, b, c) in array
+    InArray(Vec<Expr>, String),
+    // Reads the next line. True while there is input left
+    NextLine,
+    // getline, getline < file or cmd | getline. Reads into the lvalue if there is one else $0.
+    Getline(GetlineSource, Option<Box<LValue>>),
+    // name(args) for a user defined function
+    Call(String, Vec<Expr>),
+    CallBuiltin(Builtin, Vec<Expr>),
 }
 
 impl Display for Expr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Expr::Call => write!(f, "call"),
+            Expr::NextLine => write!(f, "next line"),
+            Expr::Getline(source, lvalue) => {
+                let lvalue = lvalue.as_ref().map(|l| format!(" {}", l)).unwrap_or_default();
+                match source {
+                    GetlineSource::Main => write!(f, "getline{}", lvalue),
+                    GetlineSource::File(file) => write!(f, "getline{} < {}", lvalue, file),
+                    GetlineSource::Command(cmd) => write!(f, "{} | getline{}", cmd, lvalue),
+                }
+            }
+            Expr::Call(name, args) => write!(f, "{}({})", name, args.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(", ")),
+            Expr::CallBuiltin(builtin, args) => write!(f, "{}({})", builtin.name(), args.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(", ")),
             Expr::Variable(n) => write!(f, "var {}", n),
             Expr::String(str) => write!(f, "\"{}\"", str),
+            Expr::Regex(str) => write!(f, "/{}/", str),
             Expr::NumberF64(n) => write!(f, "{}", n),
             Expr::BinOp(left, op, right) => write!(f, "{}{}{}", left, op, right),
+            Expr::Concat(parts) => write!(f, "{}", parts.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(" ")),
             Expr::LogicalOp(left, op, right) => write!(f, "{}{}{}", left, op, right),
+            Expr::Assign(lvalue, expr) => write!(f, "{} = {}", lvalue, expr),
+            Expr::AssignOp(lvalue, op, expr) => write!(f, "{} {}= {}", lvalue, op, expr),
+            Expr::Ternary(test, if_true, if_false) => write!(f, "{} ? {} : {}", test, if_true, if_false),
+            Expr::Not(expr) => write!(f, "!{}", expr),
+            Expr::UnaryMinus(expr) => write!(f, "-{}", expr),
+            Expr::UnaryPlus(expr) => write!(f, "+{}", expr),
+            Expr::PreIncrement(lvalue, delta) => write!(f, "{}{}", if *delta > 0.0 { "++" } else { "--" }, lvalue),
+            Expr::PostIncrement(lvalue, delta) => write!(f, "{}{}", lvalue, if *delta > 0.0 { "++" } else { "--" }),
             Expr::Column(col) => write!(f, "{}", col),
+            Expr::ArrayIndex(name, indices) => write!(f, "{}[{}]", name, indices.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")),
+            Expr::InArray(indices, name) => write!(f, "({}) in {}", indices.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", "), name),
         }
     }
 }
 
+// Where getline reads from
+#[derive(Debug, PartialEq, Clone)]
+pub enum GetlineSource {
+    // The main input, the files named on the command line
+    Main,
+    File(Box<Expr>),
+    Command(Box<Expr>),
+}
+
+// function name(args) { body }
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub args: Vec<String>,
+    pub body: Stmt,
+}
+
+impl Function {
+    pub fn new(name: String, args: Vec<String>, body: Stmt) -> Function { Function { name, args, body } }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Program {
     pub begins: Vec<Stmt>,
     pub ends: Vec<Stmt>,
     pub pattern_actions: Vec<PatternAction>,
+    pub functions: Vec<Function>,
 }
 
 impl Program {
-    pub fn new(begins: Vec<Stmt>, ends: Vec<Stmt>, pattern_actions: Vec<PatternAction>) -> Program { Program { begins, ends, pattern_actions } }
+    pub fn new(begins: Vec<Stmt>, ends: Vec<Stmt>, pattern_actions: Vec<PatternAction>) -> Program { Program { begins, ends, pattern_actions, functions: vec![] } }
+    pub fn new_with_functions(begins: Vec<Stmt>, ends: Vec<Stmt>, pattern_actions: Vec<PatternAction>, functions: Vec<Function>) -> Program { Program { begins, ends, pattern_actions, functions } }
     #[allow(dead_code)]
-    pub fn new_action_only(stmt: Stmt) -> Program { Program { begins: vec![], ends: vec![], pattern_actions: vec![PatternAction::new_action_only(stmt)] } }
+    pub fn new_action_only(stmt: Stmt) -> Program { Program::new(vec![], vec![], vec![PatternAction::new_action_only(stmt)]) }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use std::path::{PathBuf};
 use inkwell::memory_buffer::MemoryBuffer;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Write;
 use tempfile::{tempdir, TempDir};
 
 const RUNTIME_BITCODE: &[u8] = std::include_bytes!("../../runtime.bc");
@@ -29,9 +29,9 @@ pub fn run_and_capture(bitcode: MemoryBuffer) -> (String, String, i32) {
             println!("out_path {:?}", out_path);
             let res = std::process::Command::new(out_path).output().unwrap();
             println!("res: {:?}", res);
-            return (String::from_utf8(res.stdout).expect("stdout"), String::from_utf8(res.stderr).expect("stderr"), res.status.code().or(Some(255)).unwrap());
+            (String::from_utf8(res.stdout).expect("stdout"), String::from_utf8(res.stderr).expect("stderr"), res.status.code().unwrap_or(255))
         }
-        Err(err) => return err,
+        Err(err) => err,
     }
 }
 
@@ -42,11 +42,11 @@ pub fn external_tools(temp_dir: &TempDir, bitcode: MemoryBuffer, save_executable
 
     {
         let mut file = File::create(program_bc_path.clone()).unwrap();
-        file.write_all(bitcode.as_slice()).expect(&format!("could not write to {}", program_bc_path.to_str().unwrap()));
+        file.write_all(bitcode.as_slice()).unwrap_or_else(|_| panic!("could not write to {}", program_bc_path.to_str().unwrap()));
     }
     {
         let mut file = File::create(runtime_bc_path.clone()).unwrap();
-        file.write_all(RUNTIME_BITCODE).expect(&format!("could not write to {}", runtime_bc_path.to_str().unwrap()));
+        file.write_all(RUNTIME_BITCODE).unwrap_or_else(|_| panic!("could not write to {}", runtime_bc_path.to_str().unwrap()));
     }
 
     let args = vec!["-g", runtime_bc_path.to_str().unwrap(), program_bc_path.to_str().unwrap(), "-o", out_path.to_str().unwrap()];
@@ -69,7 +69,7 @@ pub fn external_tools(temp_dir: &TempDir, bitcode: MemoryBuffer, save_executable
             println!("{}", String::from_utf8(res.stdout).unwrap());
             eprintln!("{}", String::from_utf8(res.stderr).unwrap());
         }
-        return Err((String::from_utf8(res.stdout).unwrap(), String::from_utf8(res.stderr).unwrap(), res.status.code().or(Some(255)).unwrap()));
+        return Err((String::from_utf8(res.stdout).unwrap(), String::from_utf8(res.stderr).unwrap(), res.status.code().unwrap_or(255)));
     }
     Ok(out_path)
 }
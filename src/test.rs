@@ -3,17 +3,16 @@ use crate::{lex, parse, transform};
 use crate::codgen::compile;
 use crate::runner::{run_and_capture};
 
-const ONE_LINE: &'static str = "1 2 3\n";
-const NUMBERS: &'static str = "1 2 3\n4 5 6\n7 8 9";
-const FLOAT_NUMBERS: &'static str = "1.1 2.2 3.3\n4.4 5.5 6.6\n7.7 8.8 9.9";
+const ONE_LINE: &str = "1 2 3\n";
+const NUMBERS: &str = "1 2 3\n4 5 6\n7 8 9";
+const FLOAT_NUMBERS: &str = "1.1 2.2 3.3\n4.4 5.5 6.6\n7.7 8.8 9.9";
 
 fn run_it(program: &str, file: &str) -> (String, String, i32) {
     let temp_dir = tempdir().unwrap();
     let temp_path = temp_dir.path().join("temp_file");
     std::fs::write(&temp_path, file.as_bytes()).unwrap();
     let temp_path_str = temp_path.to_str().unwrap().to_string();
-    let r = run_and_capture(compile(transform(parse(lex(program).unwrap())), &[temp_path_str], true));
-    r
+    run_and_capture(compile(transform(parse(lex(program).unwrap())), &[temp_path_str], None, false, true))
 }
 
 fn test_it(program: &str, file: &str, output: &str, status_code: i32) {
@@ -81,4 +80,221 @@ test!(test_gteq_true, "{ if (1 >= 0) { print 123; } else {print 456;} }", ONE_LI
 test!(test_gteq_false, "{ if (0 >= 1) { print 123; } else {print 456;} }", ONE_LINE, "456\n", 0);
 
 test!(test_while, "{ x = 0; while (x < 4) { x = x + 1; print x; } print 555; }", ONE_LINE, "1\n2\n3\n4\n555\n", 0);
+test!(test_regex_pattern, "/5/ { print $1 }", NUMBERS, "4\n", 0);
+test!(test_regex_pattern_only, "/^7/", NUMBERS, "7 8 9\n", 0);
+test!(test_regex_print_no_args, "/8/ { print }", NUMBERS, "7 8 9\n", 0);
+test!(test_regex_pattern_no_match, "/abc/ { print 1 }", NUMBERS, "", 0);
+test!(test_regex_with_escaped_slash, "/2\\/3/ { print $0 }", "1/2\n2/3\n", "2/3\n", 0);
+test!(test_match_op, "$2 ~ /^[58]$/ { print $1 }", NUMBERS, "4\n7\n", 0);
+test!(test_not_match_op, "$2 !~ /5/ { print $1 }", NUMBERS, "1\n7\n", 0);
+test!(test_match_dynamic_regex, "{ re = \"^4\"; if ($0 ~ re) { print $3 } }", NUMBERS, "6\n", 0);
+test!(test_match_number_regex, "$1 ~ 7 { print $2 }", NUMBERS, "8\n", 0);
+test!(test_range, "/1/, /4/ { print $1 }", NUMBERS, "1\n4\n", 0);
+test!(test_range_same_line, "/4/, /5/ { print $1 }", NUMBERS, "4\n", 0);
+test!(test_range_reopens, "/a/, /b/", "a\nb\nc\na\nc\n", "a\nb\na\nc\n", 0);
+test!(test_two_ranges, "/1/, /4/ { print 1 } /4/, /7/ { print 2 }", NUMBERS, "1\n1\n2\n2\n", 0);
+test!(test_swap_columns, "{ tmp = $1; $1 = $2; $2 = tmp; print $0 }", ONE_LINE, "2 1 3\n", 0);
+test!(test_assign_column_past_end, "{ $5 = \"x\"; print $0 }", ONE_LINE, "1 2 3  x\n", 0);
+test!(test_assign_column_empty, "{ $3 = \"\"; print $0 }", ONE_LINE, "1 2 \n", 0);
+test!(test_assign_computed_column, "{ i = 1; $(i+1) = \"z\"; print }", ONE_LINE, "1 z 3\n", 0);
+test!(test_assign_col0_resplits, "{ $0 = \"a b\"; print $2 }", ONE_LINE, "b\n", 0);
+test!(test_copy_variable, "{ a = $1; b = a; a = 5; print b }", ONE_LINE, "1\n", 0);
+test!(test_array_assign_read, "{ a[$1] = $2 } END { print a[4] }", NUMBERS, "5\n", 0);
+test!(test_array_sum, "{ sum[\"x\"] = sum[\"x\"] + $1 } END { print sum[\"x\"] }", NUMBERS, "12\n", 0);
+test!(test_array_multi_subscript, "BEGIN { a[1, 2] = 3; if ((1, 2) in a) { print a[1, 2] } }", ONE_LINE, "3\n", 0);
+test!(test_array_in, "BEGIN { a[1] = 1; if (1 in a) { print 1 } if (2 in a) { print 2 } }", ONE_LINE, "1\n", 0);
+test!(test_array_delete, "BEGIN { a[1] = 1; a[2] = 2; delete a[1]; if (1 in a) { print 1 } if (2 in a) { print 2 } }", ONE_LINE, "2\n", 0);
+test!(test_array_delete_all, "BEGIN { a[1] = 1; a[2] = 2; delete a; if (1 in a) { print 1 } if (2 in a) { print 2 } }", ONE_LINE, "", 0);
+test!(test_array_for_in, "BEGIN { a[1] = 5; a[2] = 5; for (k in a) { sum = sum + a[k] + k } print sum }", ONE_LINE, "13\n", 0);
+test!(test_array_string_key_copy, "{ a[$1] = $0 } END { x = a[7]; delete a; print x }", NUMBERS, "7 8 9\n", 0);
+test!(test_function_recursion, "function fact(n) { if (n <= 1) { return 1 } return n * fact(n - 1) } BEGIN { print fact(5) }", ONE_LINE, "120\n", 0);
+test!(test_function_scalar_by_value, "function f(x) { x = 5 } BEGIN { y = 1; f(y); print y }", ONE_LINE, "1\n", 0);
+test!(test_function_array_by_reference, "function fill(arr) { arr[\"k\"] = 3 } BEGIN { fill(a); print a[\"k\"] }", ONE_LINE, "3\n", 0);
+test!(test_function_untyped_array_passed_through, "BEGIN { wrap(a); print a[1] } function wrap(x) { fill(x) } function fill(arr) { arr[1] = 7 }", ONE_LINE, "7\n", 0);
+test!(test_function_locals, "function f(a,   tmp) { tmp = a * 2; return tmp } BEGIN { tmp = 1; print f(3); print tmp }", ONE_LINE, "6\n1\n", 0);
+test!(test_function_local_array, "function f(n,   arr) { arr[n] = n; return arr[n] } BEGIN { print f(4); print f(5) }", ONE_LINE, "4\n5\n", 0);
+test!(test_function_string_return, "function g(s) { return s } BEGIN { x = g(\"hi\"); print x }", ONE_LINE, "hi\n", 0);
+test!(test_function_globals, "function inc() { count = count + 1 } { inc() } END { print count }", NUMBERS, "3\n", 0);
+test!(test_printf, "{ printf \"%d-%s\\n\", $1, $2 }", NUMBERS, "1-2\n4-5\n7-8\n", 0);
+test!(test_printf_parens, "BEGIN { printf(\"%5.2f|%-3d|\\n\", 3.14159, 7) }", ONE_LINE, " 3.14|7  |\n", 0);
+test!(test_printf_conversions, "BEGIN { printf \"%d %c %c %x %o %e %%\\n\", \"3abc\", 65, \"hello\", 255, 8, 1234.5 }", ONE_LINE, "3 A h ff 10 1.234500e+03 %\n", 0);
+test!(test_printf_char_of_strnum_field, "{ printf \"%c%c\\n\", $1 + 1, $1 }", "65\n", "BA\n", 0);
+test!(test_printf_char_nul_width, "BEGIN { printf \"%3c|%-3c|\", 0, 0 }", ONE_LINE, "  \0|\0  |", 0);
+test!(test_printf_star_width, "BEGIN { printf \"[%*s][%.*s]\\n\", 4, \"a\", 2, \"abc\" }", ONE_LINE, "[   a][ab]\n", 0);
+test!(test_printf_missing_args, "BEGIN { printf \"%s|%d\\n\" }", ONE_LINE, "|0\n", 0);
+test!(test_sprintf, "{ x = sprintf(\"%03d\", $3); print x }", NUMBERS, "003\n006\n009\n", 0);
+test!(test_string_escapes, "BEGIN { printf \"a\\tb\\\\\\\"\\n\" }", ONE_LINE, "a\tb\\\"\n", 0);
+test!(test_concat_fields, "{ print $1 \" - \" $2 }", NUMBERS, "1 - 2\n4 - 5\n7 - 8\n", 0);
+test!(test_concat_numbers, "BEGIN { x = 1.5; print x 2 }", ONE_LINE, "1.52\n", 0);
+test!(test_concat_precedence, "BEGIN { print 1 + 2 \"a\" }", ONE_LINE, "3a\n", 0);
+test!(test_concat_variable, "BEGIN { s = \"\" } { s = s $1 } END { print s }", NUMBERS, "147\n", 0);
+test!(test_newline_separated_statements, "{ x = $1\n y = $2\n print x y }", ONE_LINE, "12\n", 0);
+test!(test_and, "$1 > 1 && $1 < 7 { print $1 }", NUMBERS, "4\n", 0);
+test!(test_or, "$1 == 1 || $1 == 7 { print $1 }", NUMBERS, "1\n7\n", 0);
+test!(test_not, "!($1 == 4) { print $1 }", NUMBERS, "1\n7\n", 0);
+test!(test_and_short_circuits, "BEGIN { if (0 && a[1]) { print 1 } if (1 in a) { print 2 } else { print 3 } }", ONE_LINE, "3\n", 0);
+test!(test_or_short_circuits, "BEGIN { if (1 || a[1]) { print 1 } if (1 in a) { print 2 } }", ONE_LINE, "1\n", 0);
+test!(test_truthy_strings, "BEGIN { if (\"\") { print 1 } if (\"0\") { print 2 } if (!\"\") { print 3 } }", ONE_LINE, "2\n3\n", 0);
+test!(test_truthy_strnum_field, "$1 { print \"yes\" } !$1 { print \"no\" }", "0\n1\n0.0\nabc\n", "no\nyes\nno\nyes\n", 0);
+test!(test_logical_result_is_number, "BEGIN { print (2 && \"x\") (0 || \"\") }", ONE_LINE, "10\n", 0);
+test!(test_modulo, "BEGIN { print 7 % 3; print -7 % 3; print 5.5 % 2 }", ONE_LINE, "1\n-1\n1.5\n", 0);
+test!(test_pow, "BEGIN { print 2 ^ 3 ^ 2; print 2 ** 3; print 2 ^ -1 }", ONE_LINE, "512\n8\n0.5\n", 0);
+test!(test_unary_minus, "BEGIN { x = 5; print x-5; print -x; print -2 ^ 2; print - \"3\" }", ONE_LINE, "0\n-5\n-4\n-3\n", 0);
+test!(test_unary_plus, "{ print +$2 + 1 }", NUMBERS, "3\n6\n9\n", 0);
+test!(test_increment_variable, "BEGIN { i = 1; print i++; print i; print ++i; print i--; print --i }", ONE_LINE, "1\n2\n3\n3\n1\n", 0);
+test!(test_increment_column, "{ $2++; print }", NUMBERS, "1 3 3\n4 6 6\n7 9 9\n", 0);
+test!(test_increment_array_count, "{ count[$1 > 3]++ } END { print count[0] count[1] }", NUMBERS, "12\n", 0);
+test!(test_increment_array_subscript_once, "BEGIN { i = 1; a[i++]++; print i a[1] }", ONE_LINE, "21\n", 0);
+test!(test_compound_assign, "BEGIN { x = 2; x += 3; x -= 1; x *= 6; x /= 4; x %= 4; x ^= 3; print x }", ONE_LINE, "8\n", 0);
+test!(test_compound_assign_field, "{ $1 *= 10; print $1 }", NUMBERS, "10\n40\n70\n", 0);
+test!(test_assign_chain, "BEGIN { a = b = \"x\"; b = \"y\"; print a b }", ONE_LINE, "xy\n", 0);
+test!(test_assign_value, "BEGIN { print (x = 3) + 1; print x }", ONE_LINE, "4\n3\n", 0);
+test!(test_assign_in_condition, "{ if ((n = $1 * 2) > 5) { print n } }", NUMBERS, "8\n14\n", 0);
+test!(test_assign_op_value, "BEGIN { x = 1; print x += 2; print y[1] = x *= 2 }", ONE_LINE, "3\n6\n", 0);
+test!(test_assign_field_value, "{ print ($2 = \"b\") $0 }", ONE_LINE, "b1 b 3\n", 0);
+test!(test_assign_field_uses_ofs, "BEGIN { OFS = \"-\" } { $1 = $1; print; OFS = \":\"; $2 = \"b\"; print }", ONE_LINE, "1-2-3\n1:b:3\n", 0);
+test!(test_ternary, "{ print ($1 > 3 ? \"big\" : \"small\") }", NUMBERS, "small\nbig\nbig\n", 0);
+test!(test_ternary_nested, "{ x = $1 == 1 ? \"one\" : $1 == 4 ? \"four\" : \"other\"; print x }", NUMBERS, "one\nfour\nother\n", 0);
+test!(test_ternary_variable_copy, "BEGIN { s = \"a\"; t = 1 ? s : \"b\"; s = \"c\"; print t s }", ONE_LINE, "ac\n", 0);
+test!(test_ternary_short_circuits, "BEGIN { x = 1 ? 2 : a[1]; if (1 in a) { print \"bad\" } print x }", ONE_LINE, "2\n", 0);
+test!(test_for_loop, "BEGIN { for (i = 0; i < 3; i++) { print i } }", ONE_LINE, "0\n1\n2\n", 0);
+test!(test_for_loop_fields, "{ s = 0; for (i = 1; i <= 3; i++) s += $i; print s }", NUMBERS, "6\n15\n24\n", 0);
+test!(test_do_while, "BEGIN { x = 5; do { print x; x++ } while (x < 3) }", ONE_LINE, "5\n", 0);
+test!(test_do_while_loops, "BEGIN { do x += 2; while (x < 6); print x }", ONE_LINE, "6\n", 0);
+test!(test_unbraced_while, "BEGIN { i = 3; while (i > 0) i--; print i }", ONE_LINE, "0\n", 0);
+test!(test_unbraced_if_else, "{ if ($1 > 3) print \"big\"; else print \"small\" }", NUMBERS, "small\nbig\nbig\n", 0);
+test!(test_unbraced_if_else_newlines, "{ if ($1 == 4)\n print \"four\"\n else\n print \"not\" }", NUMBERS, "not\nfour\nnot\n", 0);
+test!(test_unbraced_for_in, "BEGIN { a[1] = 2; for (k in a) print k a[k] }", ONE_LINE, "12\n", 0);
+test!(test_break, "BEGIN { for (i = 0; ; i++) { if (i == 2) break; } print i }", ONE_LINE, "2\n", 0);
+test!(test_continue, "BEGIN { for (i = 0; i < 4; i++) { if (i % 2) continue; print i } }", ONE_LINE, "0\n2\n", 0);
+test!(test_continue_do_while, "BEGIN { do { i++; if (i < 3) continue; print i } while (i < 3) }", ONE_LINE, "3\n", 0);
+test!(test_break_nested, "BEGIN { while (1) { while (1) { break } x++; if (x == 2) break } print x }", ONE_LINE, "2\n", 0);
+test!(test_break_for_in, "BEGIN { a[1]; a[2]; a[3]; for (k in a) { n++; break } print n }", ONE_LINE, "1\n", 0);
+test!(test_next, "$1 == 4 { next } { print $1 }", NUMBERS, "1\n7\n", 0);
+test!(test_next_in_loop, "{ for (k in a) next; a[$1]; print $1 }", NUMBERS, "1\n", 0);
+test!(test_nextfile, "{ print $1; nextfile }", NUMBERS, "1\n", 0);
+test!(test_next_in_function, "function skip() { next } $1 == 4 { skip() } { print $1 }", NUMBERS, "1\n7\n", 0);
+test!(test_next_in_nested_function, "function inner() { next } function outer(  k) { for (k in a) inner(); print \"no\" } BEGIN { a[1] } { outer() } END { print NR }", NUMBERS, "3\n", 0);
+test!(test_nextfile_in_function, "function skip() { nextfile } { print $1; skip() }", NUMBERS, "1\n", 0);
+test!(test_next_in_function_from_begin, "function skip() { next } BEGIN { skip() }", ONE_LINE, "", 2);
+test!(test_exit_status, "BEGIN { exit 3 }", ONE_LINE, "", 3);
+test!(test_exit_runs_end, "{ print $1; exit } END { print \"end\" }", NUMBERS, "1\nend\n", 0);
+test!(test_exit_in_begin_skips_input, "BEGIN { exit } { print } END { print \"end\" }", NUMBERS, "end\n", 0);
+test!(test_exit_in_end, "END { print 1; exit; print 2 } END { print 3 }", NUMBERS, "1\n", 0);
+test!(test_exit_in_end_keeps_status, "BEGIN { exit 2 } END { exit }", ONE_LINE, "", 2);
+test!(test_exit_in_function, "function f() { exit 4 } { f(); print \"unreachable\" } END { print \"end\" }", ONE_LINE, "end\n", 4);
+test!(test_getline_command, "BEGIN { \"echo a b\" | getline; print $2 }", ONE_LINE, "b\n", 0);
+test!(test_getline_command_var, "BEGIN { \"echo x\" | getline v; print v; print (\"echo x\" | getline v) }", ONE_LINE, "x\n0\n", 0);
+test!(test_getline_command_concat, "BEGIN { cmd = \"echo\"; cmd \" hi\" | getline; print }", ONE_LINE, "hi\n", 0);
+test!(test_getline_main, "{ getline; print $1 }", NUMBERS, "4\n7\n", 0);
+test!(test_getline_main_var, "{ getline line; print $1 \"-\" line }", NUMBERS, "1-4 5 6\n7-4 5 6\n", 0);
+test!(test_getline_missing_file, "BEGIN { r = (getline line < \"/nonexistent/llawk\"); print r }", ONE_LINE, "-1\n", 0);
+test!(test_getline_file_eof, "BEGIN { while ((getline line < \"/dev/null\") > 0) n++; print n + 0 }", ONE_LINE, "0\n", 0);
+test!(test_getline_into_field_and_array, "BEGIN { \"echo z\" | getline a[1]; \"echo y\" | getline $2; print a[1] $2 }", ONE_LINE, "zy\n", 0);
+test!(test_getline_join, "BEGIN { while ((\"printf '1 one\\\\n4 four\\\\n'\" | getline) > 0) name[$1] = $2 } { print $1 name[$1] }", NUMBERS, "1one\n4four\n7\n", 0);
+test!(test_getline_file_join, "BEGIN { f = \"/tmp/llawk_getline_join\"; \"printf '1 one\\\\n' > \" f | getline; while ((getline line < f) > 0) n++; print n }", ONE_LINE, "1\n", 0);
+test!(test_getline_filename_own_stream, "NR == 1 { while ((getline line < FILENAME) > 0) n++; print n } { print $1 }", NUMBERS, "3\n1\n4\n7\n", 0);
+test!(test_close_filename_keeps_main_input, "{ getline line < FILENAME; close(FILENAME); print $1 \"-\" line }", NUMBERS, "1-1 2 3\n4-1 2 3\n7-1 2 3\n", 0);
+test!(test_print_to_file, "BEGIN { f = \"/tmp/llawk_print_to_file\"; print \"a\" > f; print \"b\" > f; close(f); while ((getline line < f) > 0) print \"got \" line }", ONE_LINE, "got a\ngot b\n", 0);
+test!(test_print_append, "BEGIN { f = \"/tmp/llawk_print_append\"; print \"a\" > f; close(f); print \"b\" >> f; close(f); while ((getline line < f) > 0) print line }", ONE_LINE, "a\nb\n", 0);
+test!(test_print_truncates_after_close, "BEGIN { f = \"/tmp/llawk_print_truncate\"; print \"a\" > f; close(f); print \"b\" > f; close(f); while ((getline line < f) > 0) print line }", ONE_LINE, "b\n", 0);
+test!(test_printf_to_file, "BEGIN { f = \"/tmp/llawk_printf_to_file\"; printf \"%d-%s\\n\", 1 + 1, \"x\" > f; close(f); getline line < f; print line }", ONE_LINE, "2-x\n", 0);
+test!(test_print_redirect_concat, "BEGIN { print \"a\" > \"/tmp/llawk_print_\" \"concat\"; close(\"/tmp/llawk_print_concat\"); getline line < \"/tmp/llawk_print_concat\"; print line }", ONE_LINE, "a\n", 0);
+test!(test_print_pipe, "{ print $1 | \"sort -r\" } END { print \"end\" }", NUMBERS, "end\n7\n4\n1\n", 0);
+test!(test_print_pipe_close, "BEGIN { print \"b\" | \"sort\"; print \"a\" | \"sort\"; close(\"sort\"); print \"done\" }", ONE_LINE, "a\nb\ndone\n", 0);
+test!(test_close_status, "BEGIN { print \"x\" | \"cat > /dev/null; exit 3\"; print close(\"cat > /dev/null; exit 3\"); print close(\"never opened\") }", ONE_LINE, "3\n-1\n", 0);
+test!(test_print_comparison_in_parens, "BEGIN { print (2 > 1) }", ONE_LINE, "1\n", 0);
+test!(test_print_to_stdout, "BEGIN { print \"a\" > \"/dev/stdout\"; fflush(); print \"b\"; fflush(\"/dev/stdout\") }", ONE_LINE, "a\nb\n", 0);
+test!(test_pipe_closed_on_exit, "BEGIN { print \"z\" | \"cat\"; exit 1 }", ONE_LINE, "z\n", 1);
+test!(test_length, "{ print length; print length(); print length($1 \"abc\") }", ONE_LINE, "5\n5\n4\n", 0);
+test!(test_length_pattern, "length > 3", "ab\nabcd\n", "abcd\n", 0);
+test!(test_substr, "BEGIN { s = \"hello\"; print substr(s, 2, 3); print substr(s, 3); print substr(s, 0, 2); print substr(s, -1); print \"[\" substr(s, 9) \"]\" }", ONE_LINE, "ell\nllo\nh\nhello\n[]\n", 0);
+test!(test_index, "BEGIN { print index(\"hello\", \"ll\"); print index(\"hello\", \"z\") }", ONE_LINE, "3\n0\n", 0);
+test!(test_split_default, "{ n = split(\"  a b\\tc \", parts); print n; print parts[1] parts[3] }", ONE_LINE, "3\nac\n", 0);
+test!(test_split_string_sep, "BEGIN { n = split(\"a:b::c\", parts, \":\"); print n; print parts[4]; print \"[\" parts[3] \"]\" }", ONE_LINE, "4\nc\n[]\n", 0);
+test!(test_split_regex_sep, "BEGIN { n = split(\"a1b22c\", parts, /[0-9]+/); for (i = 1; i <= n; i++) printf \"%s \", parts[i]; print \"\" }", ONE_LINE, "a b c \n", 0);
+test!(test_split_clears_array, "BEGIN { parts[9] = 1; split(\"x y\", parts, \" \"); print (9 in parts) }", ONE_LINE, "0\n", 0);
+test!(test_sub, "{ sub(/2/, \"two\"); print; print $2 }", ONE_LINE, "1 two 3\ntwo\n", 0);
+test!(test_gsub_count, "BEGIN { s = \"banana\"; n = gsub(/a/, \"o\", s); print n; print s }", ONE_LINE, "3\nbonono\n", 0);
+test!(test_gsub_ampersand, "BEGIN { s = \"ab\"; gsub(/[ab]/, \"[&]\", s); print s; t = \"ab\"; gsub(/a/, \"\\\\&\", t); print t }", ONE_LINE, "[a][b]\n&b\n", 0);
+test!(test_gsub_empty_matches, "BEGIN { s = \"abc\"; gsub(/x*/, \"-\", s); print s }", ONE_LINE, "-a-b-c-\n", 0);
+test!(test_sub_field_and_element, "{ a[1] = \"xx\"; sub(/x/, \"y\", a[1]); sub(/3/, \"z\", $3); print a[1]; print }", ONE_LINE, "yx\n1 2 z\n", 0);
+test!(test_sub_target_evaluated_once, "{ i = 1; a[1] = \"xx\"; a[2] = \"x\"; sub(/x/, \"y\", a[i++]); n = 1; gsub(/[0-9]/, \"d\", $(n++)); print i \" \" a[1] \" \" a[2] \" \" n; print }", ONE_LINE, "2 yx x 2\nd 2 3\n", 0);
+test!(test_sub_no_match_keeps_target, "BEGIN { s = 5; print sub(/x/, \"y\", s); print s + 1 }", ONE_LINE, "0\n6\n", 0);
+test!(test_match, "BEGIN { print match(\"foobar\", /o+/); print RSTART; print RLENGTH; print match(\"abc\", \"z\"); print RLENGTH }", ONE_LINE, "2\n2\n2\n0\n-1\n", 0);
+test!(test_case_conversion, "BEGIN { print toupper(\"aBc1\"); print tolower(\"aBc1\") }", ONE_LINE, "ABC1\nabc1\n", 0);
+test!(test_int_truncates, "BEGIN { print int(3.9); print int(-3.9); print int(\"4.5abc\") }", ONE_LINE, "3\n-3\n4\n", 0);
+test!(test_math_builtins, "BEGIN { print sqrt(16); print exp(0); print log(1); print sin(0); print cos(0); print atan2(0, -1) }", ONE_LINE, "4\n1\n0\n0\n1\n3.14159\n", 0);
+test!(test_math_on_fields, "{ print sqrt($1 + 3) }", ONE_LINE, "2\n", 0);
+test!(test_rand_range, "BEGIN { for (i = 0; i < 100; i++) { r = rand(); if (r < 0 || r >= 1) bad++ } print bad + 0 }", ONE_LINE, "0\n", 0);
+test!(test_srand_repeatable, "BEGIN { srand(42); a = rand(); srand(42); b = rand(); print (a == b) }", ONE_LINE, "1\n", 0);
+test!(test_srand_returns_previous_seed, "BEGIN { print srand(5); print srand(7.5); print srand() }", ONE_LINE, "0\n5\n7\n", 0);
+test!(test_nr, "{ print NR } END { print NR }", NUMBERS, "1\n2\n3\n3\n", 0);
+test!(test_fnr, "{ print FNR }", NUMBERS, "1\n2\n3\n", 0);
+test!(test_nf, "{ print NF }", NUMBERS, "3\n3\n3\n", 0);
+test!(test_nf_grows_with_columns, "{ $5 = \"x\"; print NF }", ONE_LINE, "5\n", 0);
+test!(test_filename_in_begin, "BEGIN { print \"[\" FILENAME \"]\" }", ONE_LINE, "[]\n", 0);
+test!(test_filename_set, "{ print (FILENAME != \"\") }", ONE_LINE, "1\n", 0);
+test!(test_match_sets_rstart_rlength, "BEGIN { match(\"foobar\", /ob/); print RSTART \" \" RLENGTH; match(\"x\", /y/); print RSTART \" \" RLENGTH }", ONE_LINE, "3 2\n0 -1\n", 0);
+test!(test_subsep, "BEGIN { SUBSEP = \":\"; a[1, 2] = 1; for (k in a) print k }", ONE_LINE, "1:2\n", 0);
+test!(test_environ, "BEGIN { print (length(ENVIRON[\"PATH\"]) > 0) }", ONE_LINE, "1\n", 0);
+test!(test_argc_argv, "BEGIN { print ARGC; print ARGV[0] }", ONE_LINE, "2\nawk\n", 0);
+test!(test_argv_replaced, "BEGIN { ARGV[1] = \"/dev/null\" } { n++ } END { print n + 0 }", NUMBERS, "0\n", 0);
+test!(test_argv_added, "BEGIN { ARGV[2] = ARGV[1]; ARGC = 3 } END { print NR \" \" FNR }", NUMBERS, "6 3\n", 0);
+test!(test_specials_case_sensitive, "{ nr = 5 } END { print nr \" \" NR }", NUMBERS, "5 3\n", 0);
+test!(test_last_column, "{ print $NF }", NUMBERS, "3\n6\n9\n", 0);
+test!(test_column_expression, "{ print $(NF-1) }", NUMBERS, "2\n5\n8\n", 0);
+test!(test_column_from_field, "{ print $$1 }", "2 a b\n", "a\n", 0);
+test!(test_nf_truncates_record, "{ NF = 2; print $0; print NF }", ONE_LINE, "1 2\n2\n", 0);
+test!(test_nf_extends_record, "{ NF = 5; print $0 \"|\"; $7 = \"x\"; print $0; print NF }", ONE_LINE, "1 2 3  |\n1 2 3    x\n7\n", 0);
+test!(test_nf_rebuild_uses_ofs, "BEGIN { OFS = \":\" } { NF = 2; print }", ONE_LINE, "1:2\n", 0);
+test!(test_nf_increment, "{ NF++; $NF = \"z\"; print }", ONE_LINE, "1 2 3 z\n", 0);
+test!(test_negative_column_fatal, "{ print $(-1) }", ONE_LINE, "", 2);
+test!(test_negative_column_unparenthesized_fatal, "{ print $-1 }", ONE_LINE, "", 2);
+test!(test_column_prefix_increment, "{ i = 1; print $++i; $i++; print $2 }", ONE_LINE, "2\n3\n", 0);
+test!(test_negative_nf_fatal, "{ NF = -1 }", ONE_LINE, "", 2);
+test!(test_nan_column_fatal, "{ print $(log(-1)) }", ONE_LINE, "", 2);
+test!(test_nan_nf_fatal, "{ NF = log(-1) }", ONE_LINE, "", 2);
+test!(test_default_fs_trims_blanks, "{ print NF \":\" $1 \":\" $3 }", "  a \t b   c  \n", "3:a:c\n", 0);
+test!(test_fs_single_char, "BEGIN { FS = \",\" } { print NF \":\" $2 }", "a,,b\n", "3:\n", 0);
+test!(test_fs_tab, "BEGIN { FS = \"\\t\" } { print $2 }", "a b\tc d\n", "c d\n", 0);
+test!(test_fs_pipe_is_literal, "BEGIN { FS = \"|\" } { print $2 }", "a|b|c\n", "b\n", 0);
+test!(test_fs_regex, "BEGIN { FS = \"[0-9]+\" } { print $1 $2 $3 }", "a12b3c\n", "abc\n", 0);
+test!(test_fs_applies_to_next_record, "{ FS = \",\"; print $1 }", "a,b c\nd,e f\n", "a,b\nd\n", 0);
+test!(test_split_uses_fs, "BEGIN { FS = \":\"; n = split(\"a:b:c\", parts); print n parts[3] }", ONE_LINE, "3c\n", 0);
+test!(test_rs_single_char, "BEGIN { RS = \";\" } { print $1 }", "a;b;c\n", "a\nb\nc\n", 0);
+test!(test_rs_paragraph_mode, "BEGIN { RS = \"\" } { print NR \":\" $1 \"-\" $NF }", "\n\na b\nc\n\n\nd e\n", "1:a-c\n2:d-e\n", 0);
+test!(test_rs_paragraph_newline_splits_fields, "BEGIN { RS = \"\"; FS = \",\" } { print NF }", "a,b\nc\n\nd\n", "3\n1\n", 0);
+test!(test_rs_regex_sets_rt, "BEGIN { RS = \"[0-9]+\" } { print $0 \"<\" RT \">\" }", "x1y22z", "x<1>\ny<22>\nz<>\n", 0);
+test!(test_rs_nul, "BEGIN { RS = \"\\0\" } { print NR \":\" $0 }", "a b\u{0}c\u{0}", "1:a b\n2:c\n", 0);
+test!(test_rs_nul_from_variable, "BEGIN { sep = \"\\0\"; RS = sep } { print NR \":\" $0 }", "a b\u{0}c\u{0}", "1:a b\n2:c\n", 0);
+test!(test_rs_nul_computed, "BEGIN { RS = substr(sprintf(\"%c\", 0) \"x\", 1, 1) } { print NR \":\" $0 }", "a b\u{0}c\u{0}", "1:a b\n2:c\n", 0);
+test!(test_deleted_nul_element, "BEGIN { a[1] = sprintf(\"%c\", 0); delete a; for (i = 0; i < 5; i++) { s = \"x\" i; printf \"%s \", s }; print \"\" }", "", "x0 x1 x2 x3 x4 \n", 0);
+test!(test_blank_line_is_a_record, "{ print NR \":\" $0 }", "a\n\nb\n", "1:a\n2:\n3:b\n", 0);
+test!(test_blank_lines_counted, "END { print NR }", "a\n\n\nb\n\n", "5\n", 0);
+test!(test_trailing_newline_not_a_record, "END { print NR }", "a\nb\n", "2\n", 0);
+test!(test_no_trailing_newline, "END { print NR }", "a\nb", "2\n", 0);
+test!(test_blank_line_nf, "{ print NF }", "a b\n\nc\n", "2\n0\n1\n", 0);
+test!(test_leading_blank_line, "{ print NR \":\" $1 }", "\nx\n", "1:\n2:x\n", 0);
+test!(test_getline_blank_record, "NR == 1 { getline; print \"[\" $0 \"]\" }", "a\n\nb\n", "[]\n", 0);
+test!(test_end_keeps_last_record, "END { print $0 }", NUMBERS, "7 8 9\n", 0);
+test!(test_empty_input, "{ n++ } END { print n + 0 }", "", "0\n", 0);
+test!(test_compare_strings, "BEGIN { print (\"10\" < \"9\"); print (\"abc\" < \"abd\"); print (\"a\" == \"a\") }", ONE_LINE, "1\n1\n1\n", 0);
+test!(test_compare_fields_numeric, "{ print ($1 < $2) }", "10 9\n9 10\n", "0\n1\n", 0);
+test!(test_compare_field_to_string, "{ print ($1 == \"abc\") }", "abc\n0\n", "1\n0\n", 0);
+test!(test_compare_strnum_to_number, "{ print ($1 == 1) }", " 1.0 \n1e0\nx\n", "1\n1\n0\n", 0);
+test!(test_compare_number_to_string, "BEGIN { x = 2; print (x < \"10\") }", ONE_LINE, "0\n", 0);
+test!(test_compare_uninitialized, "BEGIN { print (x == \"\"); print (x == 0); print (a[1] == 0); print (a[2] == \"\") }", ONE_LINE, "1\n1\n1\n1\n", 0);
+test!(test_uninitialized_prints_empty, "BEGIN { print \"[\" x \"]\"; print x; print x + 0 }", ONE_LINE, "[]\n\n0\n", 0);
+test!(test_compare_getline_strnum, "BEGIN { \"echo 10\" | getline v; print (v > 9) }", ONE_LINE, "1\n", 0);
+test!(test_compare_split_strnum, "BEGIN { split(\"10 9\", p); print (p[1] > p[2]) }", ONE_LINE, "1\n", 0);
+test!(test_string_to_number_exponent, "BEGIN { print \"1e3\" + 2; print 1.5e-3 * 1000 }", ONE_LINE, "1002\n1.5\n", 0);
+test!(test_string_to_number_prefix, "BEGIN { print \" 12abc\" + 0; print \"-3.5x\" * 2; print \".5\" + 0; print \"abc\" + 1 }", ONE_LINE, "12\n-7\n0.5\n1\n", 0);
+test!(test_string_to_number_no_hex, "BEGIN { print \"0x1A\" + 0; print \"inf\" + 0; print \"nan\" + 0 }", ONE_LINE, "0\n0\n0\n", 0);
+test!(test_field_exponent_strnum, "{ print ($1 == 1000) }", "1e3\n", "1\n", 0);
+test!(test_print_large_integers, "BEGIN { print 3000000000; print -3000000000; print 2 ^ 53; print 2 ^ 53 * 4 }", ONE_LINE, "3000000000\n-3000000000\n9007199254740992\n3.60288e+16\n", 0);
+test!(test_ofmt, "BEGIN { OFMT = \"%.2f\"; print 3.14159; print 3.14159 \"\"; print 10 }", ONE_LINE, "3.14\n3.14159\n10\n", 0);
+test!(test_convfmt, "BEGIN { CONVFMT = \"%.2f\"; x = 3.14159 \"\"; print x; a[0.123] = 1; for (k in a) print k; print 3.14159 }", ONE_LINE, "3.14\n0.12\n3.14159\n", 0);
+test!(test_convfmt_default, "BEGIN { print CONVFMT; print OFMT; print 0.1 + 0.2 \"\" }", ONE_LINE, "%.6g\n%.6g\n0.3\n", 0);
+test!(test_capitalized_keywords_are_names, "BEGIN { In = 1; Next = 2; Function = 3; print In + Next + Function }", ONE_LINE, "6\n", 0);
 // test!(test_if_no_else_truthy, "{if (1) { print "truthy"; }}", ONE_LINE, "truthy\n", 0);
@@ -1,27 +1,45 @@
 use crate::{Expr, parser};
-use crate::parser::{Stmt};
+use crate::parser::{Function, LValue, Pattern, Stmt};
 
-pub fn transform(program: parser::Program) -> Stmt {
+// The main program with every pattern lowered into a single statement, the END blocks (kept
+// separate so `exit` can run them from anywhere) and the user functions
+pub struct LoweredProgram {
+    pub main: Stmt,
+    pub end: Stmt,
+    pub functions: Vec<Function>,
+}
+
+pub fn transform(program: parser::Program) -> LoweredProgram {
     let mut prog = program.begins;
 
     let mut every_line_stms = vec![];
-    for pattern in program.pattern_actions {
-        let stmt = if let Some(test) = pattern.pattern {
-            Stmt::While(test, Box::new(pattern.action))
-        } else {
-            pattern.action
+    for (idx, pattern) in program.pattern_actions.into_iter().enumerate() {
+        let stmt = match pattern.pattern {
+            None => pattern.action,
+            Some(Pattern::Expr(test)) => Stmt::If(test, Box::new(pattern.action), None),
+            Some(Pattern::Range(start, end)) => range(idx, start, end, pattern.action),
         };
         every_line_stms.push(stmt)
     }
-    if every_line_stms.len() > 0 {
-        let line_loop = Stmt::While(Expr::Call, Box::new(Stmt::Group(every_line_stms)));
+    if !every_line_stms.is_empty() {
+        let line_loop = Stmt::While(Expr::NextLine, Box::new(Stmt::Group(every_line_stms)));
         prog.push(line_loop);
     }
 
-    for end in program.ends {
-        prog.push(end);
-    }
-
     println!("{:?}", prog);
-    Stmt::Group(prog)
-}
\ No newline at end of file
+    LoweredProgram { main: Stmt::Group(prog), end: Stmt::Group(program.ends), functions: program.functions }
+}
+
+// Each range pattern gets its own hidden flag which is set while inside the range. The flag's name
+// is not a valid awk identifier so it can't collide with a user variable.
+//   if (flag) {} else if (start) { flag = 1 }
+//   if (flag) { if (end) { flag = 0 } action }
+// A line matching both start and end runs the action once and leaves the range closed.
+fn range(idx: usize, start: Expr, end: Expr, action: Stmt) -> Stmt {
+    let flag = format!("range-{}", idx);
+    let enter = Stmt::If(start, Box::new(Stmt::Expr(Expr::Assign(Box::new(LValue::Variable(flag.clone())), Box::new(Expr::NumberF64(1.0))))), None);
+    let enter = Stmt::If(Expr::Variable(flag.clone()), Box::new(Stmt::Group(vec![])), Some(Box::new(enter)));
+    let leave = Stmt::If(end, Box::new(Stmt::Expr(Expr::Assign(Box::new(LValue::Variable(flag.clone())), Box::new(Expr::NumberF64(0.0))))), None);
+    let body = Stmt::If(Expr::Variable(flag), Box::new(Stmt::Group(vec![leave, action])), None);
+    Stmt::Group(vec![enter, body])
+}